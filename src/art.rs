@@ -0,0 +1,62 @@
+use image::{imageops::FilterType, DynamicImage, GenericImageView as _};
+
+use crate::statejson::ThumbnailState;
+
+// The thumbnail to draw `cols` columns wide from: the smallest that's at least that many pixels wide,
+// so nothing has to be scaled up, or the largest there is if none are.
+pub fn pick_thumbnail<'t, 'a>(thumbnails: &'t [ThumbnailState<'a>], cols: u32) -> Option<&'t ThumbnailState<'a>> {
+	thumbnails.iter()
+		.filter(|t| t.width >= cols)
+		.min_by_key(|t| t.width)
+		.or_else(|| thumbnails.iter().max_by_key(|t| t.width))
+}
+
+// Draws `image` `cols` columns wide with `▀`, its foreground colour the pixel above and its background the one below,
+// so each line of text shows two rows of pixels. Needs a terminal with 24-bit colour.
+pub fn render(image: &DynamicImage, cols: u32) -> String {
+	let (width, height) = image.dimensions();
+	// two pixel rows to a line, and at least one line
+	let rows = ((u64::from(height) * u64::from(cols) / u64::from(width.max(1))) as u32).div_ceil(2).max(1) * 2;
+	let scaled = image.resize_exact(cols, rows, FilterType::Triangle).to_rgb8();
+	let mut out = String::new();
+	for y in (0..rows).step_by(2) {
+		for x in 0..cols {
+			let [tr, tg, tb] = scaled.get_pixel(x, y).0;
+			let [br, bg, bb] = scaled.get_pixel(x, y + 1).0;
+			out.push_str(&format!("\x1b[38;2;{tr};{tg};{tb}m\x1b[48;2;{br};{bg};{bb}m▀"));
+		}
+		out.push_str("\x1b[0m\n");
+	}
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use image::{Rgb, RgbImage};
+
+	use super::*;
+
+	#[test]
+	fn thumbnails_are_picked_by_width() {
+		let thumbnails = [
+			ThumbnailState { url: "small", width: 60, height: 60 },
+			ThumbnailState { url: "large", width: 544, height: 544 },
+			ThumbnailState { url: "medium", width: 120, height: 120 },
+		];
+		assert_eq!(pick_thumbnail(&thumbnails, 40).unwrap().url, "small");
+		assert_eq!(pick_thumbnail(&thumbnails, 100).unwrap().url, "medium");
+		assert_eq!(pick_thumbnail(&thumbnails, 1000).unwrap().url, "large");
+		assert!(pick_thumbnail(&[], 40).is_none());
+	}
+
+	#[test]
+	fn pixels_are_drawn_two_rows_to_a_line() {
+		let mut image = RgbImage::new(2, 2);
+		image.put_pixel(0, 0, Rgb([255, 0, 0]));
+		image.put_pixel(1, 0, Rgb([0, 255, 0]));
+		image.put_pixel(0, 1, Rgb([0, 0, 255]));
+		image.put_pixel(1, 1, Rgb([255, 255, 255]));
+		let rendered = render(&DynamicImage::ImageRgb8(image), 2);
+		assert_eq!(rendered, "\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m▀\x1b[38;2;0;255;0m\x1b[48;2;255;255;255m▀\x1b[0m\n");
+	}
+}
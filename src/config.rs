@@ -0,0 +1,123 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::Deserialize;
+
+use crate::server::ServerAddr;
+
+// `config.toml` in the config directory. Everything in it is optional, and a missing file is an empty config.
+#[derive(Debug, Default)]
+pub struct Config {
+	// `[servers]`: names usable with `--server`, each standing for an address like `192.168.1.42` or `host:9864`
+	pub servers: HashMap<String, ServerAddr>,
+	// `[commands.<command>]`: options each command gets as if they were written before the ones actually given,
+	// as the arguments they stand for (`quiet = true` is `--quiet`, `max-volume = 80` is `--max-volume 80`)
+	pub commands: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+	#[serde(default)]
+	servers: HashMap<String, String>,
+	#[serde(default)]
+	commands: HashMap<String, toml::Table>,
+}
+
+impl Config {
+	pub fn load(path: &Path) -> Result<Config, String> {
+		let contents = match std::fs::read_to_string(path) {
+			Ok(contents) => contents,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+			Err(e) => return Err(e.to_string()),
+		};
+		Config::parse(&contents)
+	}
+
+	pub fn parse(contents: &str) -> Result<Config, String> {
+		let raw: RawConfig = toml::from_str(contents).map_err(|e| e.to_string())?;
+		let mut servers = HashMap::new();
+		for (alias, addr) in raw.servers {
+			let Ok(addr) = addr.parse() else {
+				return Err(format!("invalid address `{addr}` for server `{alias}`"));
+			};
+			servers.insert(alias, addr);
+		}
+		let mut commands = HashMap::new();
+		for (command, options) in raw.commands {
+			let mut args = Vec::new();
+			for (option, value) in options {
+				option_args(&mut args, &option, &value).map_err(|e| format!("{e} for `{option}` in `[commands.{command}]`"))?;
+			}
+			commands.insert(command, args);
+		}
+		Ok(Config { servers, commands })
+	}
+
+	// The arguments `[commands.<command>]` adds, in front of the command's own.
+	pub fn command_defaults(&self, command: &str) -> &[String] {
+		self.commands.get(command).map_or(&[], Vec::as_slice)
+	}
+
+	// The address `--server` stands for: what it's an alias of, or just itself.
+	pub fn resolve_server<'a>(&'a self, addr: &'a ServerAddr) -> &'a ServerAddr {
+		match addr.port {
+			None => self.servers.get(&addr.host).unwrap_or(addr),
+			// `alias:port` isn't an alias, since the alias already says which port to use
+			Some(_) => addr,
+		}
+	}
+}
+
+// Turns one option from a `[commands]` table into arguments. An array gives the option once for each element.
+fn option_args(args: &mut Vec<String>, option: &str, value: &toml::Value) -> Result<(), String> {
+	let flag = format!("--{option}");
+	match value {
+		toml::Value::Boolean(true) => args.push(flag),
+		toml::Value::Boolean(false) => (),
+		toml::Value::String(s) => args.extend([flag, s.clone()]),
+		toml::Value::Integer(n) => args.extend([flag, n.to_string()]),
+		toml::Value::Float(n) => args.extend([flag, n.to_string()]),
+		toml::Value::Array(values) => {
+			for value in values {
+				if matches!(value, toml::Value::Array(_)) {
+					return Err(String::from("nested array"));
+				}
+				option_args(args, option, value)?;
+			}
+		},
+		toml::Value::Datetime(_) | toml::Value::Table(_) => return Err(format!("unsupported {} value", value.type_str())),
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn server_aliases_resolve() {
+		let config = Config::parse("[servers]\nliving-room = \"192.168.1.42\"\nden = \"http://den.local:9000/\"\n").unwrap();
+		let alias: ServerAddr = "living-room".parse().unwrap();
+		assert_eq!(config.resolve_server(&alias).host, "192.168.1.42");
+		let den: ServerAddr = "den".parse().unwrap();
+		assert_eq!(config.resolve_server(&den), &ServerAddr { host: String::from("den.local"), port: Some(9000) });
+		let literal: ServerAddr = "10.0.0.1".parse().unwrap();
+		assert_eq!(config.resolve_server(&literal), &literal);
+	}
+
+	#[test]
+	fn bad_configs_are_rejected() {
+		assert!(Config::parse("[servers]\nden = \"not a host\"\n").is_err());
+		assert!(Config::parse("[servre]\n").is_err());
+		assert!(Config::parse("").unwrap().servers.is_empty());
+		assert!(Config::parse("[commands.state]\nfield = { a = 1 }\n").is_err());
+	}
+
+	#[test]
+	fn command_defaults_become_arguments() {
+		let config = Config::parse("[commands.volume-up]\nmax-volume = 80\nconfirm = false\n[commands.play]\nquiet = true\nheader = [\"A: 1\", \"B: 2\"]\n").unwrap();
+		assert_eq!(config.command_defaults("volume-up"), ["--max-volume", "80"]);
+		assert_eq!(config.command_defaults("play"), ["--header", "A: 1", "--header", "B: 2", "--quiet"]);
+		assert!(config.command_defaults("state").is_empty());
+	}
+}
@@ -1,349 +1,1020 @@
-use std::{collections::HashMap, fs::Permissions, io::Write, path::{Path, PathBuf}};
+use std::{collections::HashMap, io::Write, path::{Path, PathBuf}, sync::atomic::{AtomicBool, Ordering}};
 
-use arg::Args;
+use arg::{Args, ParseError, ParseKind};
+use config::Config;
 use once_cell::sync::OnceCell;
-use reqwest::StatusCode;
+use reqwest::{header::{HeaderName, HeaderValue}, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use statejson::StateResponse;
+use server::ServerAddr;
+use statejson::{LikeState, PlaybackState, QueueState, StateResponse, VideoType};
+use template::Template;
+use timefmt::{format_duration, unknown_duration, usable_duration, Timestamp};
+use tokenstore::{owner_only, FileTokenStore, TokenStore};
 
+#[cfg(feature = "image")]
+mod art;
+mod config;
+#[cfg(test)]
+mod mock;
+mod server;
 mod statejson;
+mod template;
+mod timefmt;
+mod tokenstore;
+
+const DEFAULT_PORT: u16 = 9863;
+// Version of the companion server API every request is made against, as in `/api/v1/state`
+const API_VERSION: &str = "v1";
+// How ytmdctrl introduces itself when requesting authorization
+const DEFAULT_APP_ID:   &str = "ytmdctrl";
+const DEFAULT_APP_NAME: &str = "Seta's YTMD CLI";
+// Sent with every request, so the server can tell which client and version it's talking to.
+const USER_AGENT: &str = concat!("ytmdctrl/", env!("CARGO_PKG_VERSION"));
+
+// Kept short so an unreachable address (e.g. the wrong address family) fails fast instead of hanging.
+const CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+// How long `open` and `jumpto --wait-generate` wait for the queue to load before giving up on it.
+const QUEUE_LOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(20);
+// How long `play --wait-until-playing` gives playback to start.
+const PLAYBACK_START_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(20);
+// Most skips `next`/`previous` will make in one go; past this it's likelier a typo than intended.
+const MAX_SKIPS: u32 = 50;
+// Pause between commands sent back to back, like the skips of `next <count>`, so as not to trip YTMD's rate limit,
+// which allows about two commands a second.
+const COMMAND_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
 
 const ERR_INVALID_ARGS:   i32 = 1;
 const ERR_COMMAND_FAILED: i32 = 2;
+const ERR_NOT_CONFIRMED:  i32 = 3;
+const ERR_FIELD_MISSING:  i32 = 4;
+// Not an error, but distinct from success so scripts can branch on it
+const EXIT_UNCHANGED:     i32 = 10;
+// `is-liked` maps the like status straight to the exit code
+const EXIT_LIKED:         i32 = 0;
+const EXIT_INDIFFERENT:   i32 = 1;
+const EXIT_DISLIKED:      i32 = 2;
+const EXIT_LIKE_UNKNOWN:  i32 = 11;
+
+// How a command ended short of simply succeeding. It's handed back up to whatever ran the command,
+// so that `run` and `daemon` can carry on with the next one instead of the process ending.
+enum Stop {
+	// Exit with this code.
+	Exit(i32),
+	// YTMD rejected the token, so it's of no use for this command or any after it.
+	TokenRejected,
+	// YTMD turned the command away for coming too soon after others.
+	RateLimited,
+}
 
-#[derive(Debug, Args, Clone)]
-struct BaseArgs {
-	#[arg(short = "p", long = "delay")]
-	delay: Option<String>,
-	#[arg(short = "s", long = "server", default_value = "\"localhost\".to_string()")]
-	server_addr: String,
-	#[arg(short = "c", long = "script")]
-	// Only relevant for commands with output.
-	// if true use parser-friendly output,
-	// otherwise output in a human-friendly way.
-	script_mode: bool,
+impl Stop {
+	// The code the process exits with when a command stops like this at the top level.
+	fn code(&self) -> i32 {
+		match self {
+			Stop::Exit(code) => *code,
+			// the rejection has already been reported, and the token is dealt with by `run_authorized`
+			Stop::TokenRejected => 0,
+			// already reported along with how long to wait, and retrying later is all there is to do about it
+			Stop::RateLimited => 0,
+		}
+	}
 }
 
+// What `main_logic` and the helpers it runs commands through return.
+type Outcome<T = ()> = Result<T, Stop>;
 
-#[derive(Debug, Args, Clone)]
-struct VideoChangeRequestArgs {
-	#[arg(short = "v", long = "video")]
-	video: Option<String>, 
-	#[arg(short = "l", long = "playlist")]
-	playlist: Option<String>,
+// Ends the whole process, whatever command is running, logging it for `--log` first.
+fn exit_process(code: i32) -> ! {
+	log_run(code, None);
+	std::process::exit(code)
+}
 
-	#[arg(short = "p", long = "delay")]
-	delay: Option<String>,
-	#[arg(short = "s", long = "server", default_value = "\"localhost\".to_string()")]
-	server_addr: String,
+// Where `--log` records how this run ended, and what to record it as.
+struct RunLog {
+	path: PathBuf,
+	command: String,
+	server: String,
 }
 
+static RUN_LOG: OnceCell<RunLog> = OnceCell::new();
 
-#[derive(Debug, Args, Clone)]
-struct SetFloatArgs {
-	#[arg(required)]
-	target: f32,
+// Appends a line about how the run ended to the `--log` file, if one was given. `outcome` defaults to the exit code.
+fn log_run(code: i32, outcome: Option<&str>) {
+	let Some(log) = RUN_LOG.get() else {
+		return;
+	};
+	let time = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false);
+	let outcome = outcome.map_or_else(|| format!("exit {code}"), |outcome| format!("{outcome}, exit {code}"));
+	let mut options = std::fs::OpenOptions::new();
+	options.append(true).create(true);
+	#[cfg(target_family="unix")]
+	{
+		use std::os::unix::fs::OpenOptionsExt as _;
+		options.mode(0o600);
+	}
+	let written = options.open(&log.path)
+		.and_then(|mut file| writeln!(file, "{time} `{}` on {}: {outcome}", log.command, log.server));
+	if let Err(e) = written {
+		eprintln!("Unable to write to the log {}: {e}", log.path.display());
+	}
+}
+
+// Declares an arguments struct, appending the options every command accepts to its own fields.
+// Keeping them in one place keeps `CommonArgs` and the generated help in sync across commands.
+macro_rules! command_args {
+	($(#[$meta:meta])* struct $name:ident { $($fields:tt)* }) => {
+		$(#[$meta])*
+		#[derive(Debug, Args, Clone)]
+		struct $name {
+			$($fields)*
+			#[arg(short = "p", long = "delay")]
+			/// Wait before running: seconds (`5`), a duration (`30s`, `2m`, `1h30m`), or `until HH:MM[:SS]` for the next time the local clock reads that.
+			delay: Option<String>,
+			#[arg(short = "s", long = "server", default_value = "ServerAddr::localhost()")]
+			/// Server to connect to, optionally with a port (`host:1234`), or a name from `[servers]` in `config.toml`. Default is `localhost`.
+			server_addr: ServerAddr,
+			#[arg(long = "port", default_value = "DEFAULT_PORT")]
+			/// Port of the companion server. Default is `9863`.
+			port: u16,
+			#[arg(short = "4", long = "ipv4")]
+			/// Only connect to the server over IPv4.
+			ipv4: bool,
+			#[arg(short = "6", long = "ipv6")]
+			/// Only connect to the server over IPv6.
+			ipv6: bool,
+			#[arg(long = "app-name")]
+			/// Name shown in YTMD's list of authorized apps. Default is `Seta's YTMD CLI`.
+			app_name: Option<String>,
+			#[arg(long = "app-id")]
+			/// App id to request authorization under. Default is `ytmdctrl`. Tokens are tied to it, so changing it means reauthorizing.
+			app_id: Option<String>,
+			#[arg(long = "header")]
+			/// Extra header to send with every request, as `Name: value`, e.g. for a proxy in front of YTMD. Can be given more than once.
+			headers: Vec<String>,
+			#[arg(long = "via-daemon")]
+			/// Hand the command to a running `ytmdctrl daemon`, or run it directly if there isn't one.
+			via_daemon: bool,
+			#[arg(long = "no-pretty")]
+			/// Print JSON output on a single line rather than indented, for line-based tools.
+			no_pretty: bool,
+			#[arg(long = "dump-headers")]
+			/// Print the headers of every response from YTMD to stderr, e.g. to check on its rate limit.
+			dump_headers: bool,
+			#[arg(long = "log")]
+			/// Append a timestamped line to this file saying what ran, against which server, and how it ended.
+			log: Option<PathBuf>,
+			#[arg(long = "wait-connection")]
+			/// If YTMD can't be reached yet, e.g. just after boot, keep trying for up to this long, as a `--delay`-style duration.
+			wait_connection: Option<String>,
+		}
 
-	#[arg(short = "p", long = "delay")]
-	delay: Option<String>,
-	#[arg(short = "s", long = "server", default_value = "\"localhost\".to_string()")]
-	server_addr: String,
+		impl CommonArgs for $name {
+			fn delay(&self) -> Option<&str> { self.delay.as_deref() }
+			fn server_addr(&self) -> &str { &get_config().resolve_server(&self.server_addr).host }
+			fn url_host(&self) -> String { get_config().resolve_server(&self.server_addr).url_host() }
+			// a port given as part of `--server` (or its alias) wins over `--port`
+			fn port(&self) -> u16 { get_config().resolve_server(&self.server_addr).port.unwrap_or(self.port) }
+			fn ipv4(&self) -> bool { self.ipv4 }
+			fn ipv6(&self) -> bool { self.ipv6 }
+			fn app_name(&self) -> &str { self.app_name.as_deref().unwrap_or(DEFAULT_APP_NAME) }
+			fn app_id(&self) -> &str { self.app_id.as_deref().unwrap_or(DEFAULT_APP_ID) }
+			fn headers(&self) -> &[String] { &self.headers }
+			fn via_daemon(&self) -> bool { self.via_daemon }
+			fn pretty(&self) -> bool { !self.no_pretty }
+			fn dump_headers(&self) -> bool { self.dump_headers }
+			fn log(&self) -> Option<&Path> { self.log.as_deref() }
+			fn wait_connection(&self) -> Option<&str> { self.wait_connection.as_deref() }
+		}
+	};
+}
+
+// Accessors for the options `command_args!` adds to every arguments struct.
+trait CommonArgs {
+	fn delay(&self) -> Option<&str>;
+	fn server_addr(&self) -> &str;
+	fn url_host(&self) -> String;
+	fn port(&self) -> u16;
+	fn ipv4(&self) -> bool;
+	fn ipv6(&self) -> bool;
+	fn app_name(&self) -> &str;
+	fn app_id(&self) -> &str;
+	fn headers(&self) -> &[String];
+	fn via_daemon(&self) -> bool;
+	fn pretty(&self) -> bool;
+	fn dump_headers(&self) -> bool;
+	fn log(&self) -> Option<&Path>;
+	fn wait_connection(&self) -> Option<&str>;
+}
+
+command_args! {
+	/// Options for commands without arguments.
+	struct BaseArgs {
+		#[arg(short = "c", long = "script")]
+		/// Use parser-friendly output instead of human-friendly output, e.g. times in full-precision seconds.
+		script_mode: bool,
+		#[arg(long = "confirm")]
+		/// For `mute`/`unmute`: re-read the player state afterwards and exit with code 3 if the change didn't take effect.
+		confirm: bool,
+		#[arg(short = "f", long = "field")]
+		/// For `state`: print only the value at a dotted path such as `volume`, `video.title` or `queue.selected_index`. Exits with code 4 if there is no such value.
+		field: Option<String>,
+		#[arg(short = "q", long = "quiet")]
+		/// Print nothing, leaving just the exit code, for commands like `is-liked`.
+		quiet: bool,
+		#[arg(long = "since")]
+		/// For `state`: the video id from a previous run. Prints the state only if the song has changed since, otherwise exits with code 10.
+		since: Option<String>,
+		#[arg(long = "compact")]
+		/// For `state`: print a single line with the song, progress and volume.
+		compact: bool,
+		#[arg(long = "no-unicode")]
+		/// For `state`: use plain ASCII instead of symbols for the playback status and the selected song.
+		no_unicode: bool,
+		#[arg(short = "y", long = "yes")]
+		/// For `shuffle`: don't ask for confirmation first. Required in script mode.
+		yes: bool,
+		#[arg(long = "json")]
+		/// For `track-info`: print a JSON object instead of `key=value` lines.
+		json: bool,
+		#[arg(long = "wait-until-playing")]
+		/// For `play`: wait for playback to actually start, exiting with code 3 if it doesn't within 20s.
+		wait_until_playing: bool,
+		#[arg(long = "no-automix")]
+		/// For `state`: leave out the automix queue.
+		no_automix: bool,
+		#[arg(long = "automix-only")]
+		/// For `state`: show only the automix queue.
+		automix_only: bool,
+		#[arg(long = "template")]
+		/// For `state`: render the state with the template in this file. See the README for its placeholders.
+		template: Option<PathBuf>,
+		#[arg(long = "art-width")]
+		/// For `state`: draw the album art above the state, this many columns wide. Needs a build with the `image` feature.
+		art_width: Option<u32>,
+	}
+}
+
+command_args! {
+	/// Change the current song and/or playlist. At least one of --video and --playlist must be given.
+	struct VideoChangeRequestArgs {
+		#[arg(short = "v", long = "video")]
+		/// Id of the song to play.
+		video: Option<String>,
+		#[arg(short = "l", long = "playlist")]
+		/// Id of the playlist to play.
+		playlist: Option<String>,
+		#[arg(short = "i", long = "index")]
+		/// Position in the queue to start at, once it has loaded.
+		index: Option<u32>,
+		#[arg(long = "play")]
+		/// Start playback once the queue has loaded, in case YTMD doesn't on its own.
+		play: bool,
+	}
+}
+
+command_args! {
+	/// Commands taking a single number.
+	struct SetFloatArgs {
+		#[arg(required)]
+		/// Volume percentage.
+		target: f32,
+	}
+}
+
+command_args! {
+	/// Increase the volume by a step.
+	struct VolumeUpArgs {
+		#[arg(long = "max-volume")]
+		/// Never go above this percentage, setting it exactly if a step would overshoot.
+		max_volume: Option<u8>,
+	}
+}
+
+command_args! {
+	/// Decrease the volume by a step.
+	struct VolumeDownArgs {
+		#[arg(long = "min-volume")]
+		/// Never go below this percentage, setting it exactly if a step would overshoot.
+		min_volume: Option<u8>,
+	}
+}
+
+command_args! {
+	/// Jump to a place in the queue.
+	struct JumptoArgs {
+		#[arg(required)]
+		/// Queue index to jump to.
+		target: f32,
+		#[arg(long = "wait-generate")]
+		/// If the queue is still being generated and doesn't reach the index yet, wait for it first.
+		wait_generate: bool,
+	}
+}
+
+command_args! {
+	/// Commands that can be repeated a number of times.
+	struct SkipArgs {
+		/// How many songs to skip. Default is 1.
+		count: Option<u32>,
+	}
+}
+
+command_args! {
+	/// Keep reporting the player's status.
+	struct WatchArgs {
+		#[arg(long = "fifo")]
+		/// Write to this named pipe instead of stdout, reopening it whenever its reader goes away.
+		fifo: Option<PathBuf>,
+	}
+}
+
+command_args! {
+	/// Run commands from a file.
+	struct RunArgs {
+		#[arg(required)]
+		/// File with one command per line, such as `volume 30`. Lines starting with # are comments.
+		script: PathBuf,
+		#[arg(long = "continue-on-error")]
+		/// Keep going after a line fails instead of stopping there.
+		continue_on_error: bool,
+	}
+}
+
+command_args! {
+	/// Send a command body as-is.
+	struct RawArgs {
+		#[arg(required)]
+		/// The JSON body to post, with a `command` and optionally `data`, as documented for the YTMD companion server.
+		body: String,
+	}
+}
+
+command_args! {
+	/// Fetch an API path as-is.
+	struct GetArgs {
+		#[arg(required)]
+		/// Path under the companion API root, such as `playlists`.
+		path: String,
+	}
+}
+
+command_args! {
+	/// Seek within the current song.
+	struct SeekArgs {
+		#[arg(required)]
+		/// Position to seek to, as `ss`, `mm:ss` or `hh:mm:ss`.
+		target: Timestamp,
+		#[arg(long = "force")]
+		/// Skip checking whether the current song is a live stream.
+		force: bool,
+	}
+}
+
+command_args! {
+	/// Wait for the current song to end.
+	struct WaitArgs {
+		#[arg(long = "poll-interval")]
+		/// How often to check on the player, as a `--delay`-style duration. Default is `5s`; much less risks YTMD's rate limit.
+		poll_interval: Option<String>,
+		#[arg(long = "timeout")]
+		/// Give up after this long, as a `--delay`-style duration. Default is the rest of the song plus a minute.
+		timeout: Option<String>,
+	}
+}
+
+command_args! {
+	/// Repeat part of a song. Timestamps are `ss`, `mm:ss` or `hh:mm:ss`.
+	struct LoopArgs {
+		#[arg(required)]
+		/// Where to seek back to.
+		start: Timestamp,
+		#[arg(required)]
+		/// Where to seek back from.
+		end: Timestamp,
+	}
 }
 
+// Doc comments here make up the command list in `--help`.
 #[derive(Debug, Clone, Args)]
 enum Command {
-	// Get information about what the player is currently doing, what songs are playing, etc.
 	// Once per 5s
+	/// Show what the player is doing: the current song, progress, volume and queue.
 	State(BaseArgs),
-	// Get a list of all the user's playlists.
+	/// Show the time left in the queue, including the rest of the current song. A trailing + means the queue is still growing.
+	Remaining(BaseArgs),
+	/// Print the current song's details as `key=value` lines, for scrobblers and the like.
+	TrackInfo(BaseArgs),
+	/// Wait until the current song ends or playback stops. Exits with code 10 if it's still playing when the timeout runs out.
+	WaitTrackEnd(WaitArgs),
+	/// Print a line with the current song whenever it or the playback status changes, until interrupted. Meant for status bars.
+	Watch(WatchArgs),
 	// Once per 30s
+	/// List all playlists in the user's account. With --script, each line is the id and the title separated by a tab, with tabs, newlines and backslashes in the title escaped as in C.
 	Playlists(BaseArgs),
 
 	// All remaining commands are Twice per 1s
 
-	// Toggles playback state (default command).
+	/// Toggle playback (default command).
 	PlayPause(BaseArgs),
-	// Unpauses/starts playback (as long as something is currently ready to play).
+	/// Resume/start playback (as long as something is ready to play).
 	Play(BaseArgs),
-	// Pauses playback.
+	/// Pause playback.
 	Pause(BaseArgs),
-	// Increase volume.
-	VolumeUp(BaseArgs),
-	// Decrease volume.
-	VolumeDown(BaseArgs),
-	// Set Volume to a specific percentage between 0 and 100.
+	/// Start playback unless it's already playing, checking first rather than toggling.
+	EnsurePlaying(BaseArgs),
+	/// Pause playback unless it's already paused, checking first rather than toggling.
+	EnsurePaused(BaseArgs),
+	/// Increase volume.
+	VolumeUp(VolumeUpArgs),
+	/// Decrease volume.
+	VolumeDown(VolumeDownArgs),
+	/// <target>: Set the volume to <target> percent, between 0 and 100.
 	Volume(SetFloatArgs),
-	// Mutes playback.
+	/// Mute playback.
 	Mute(BaseArgs),
-	// Unmutes playback.
+	/// Unmute playback.
 	Unmute(BaseArgs),
-	// Seek to a specific number of seconds into the song.
-	Seek(SetFloatArgs),
-	// Skip to next song.
-	Next(BaseArgs),
-	// Restart song or go to previous song.
-	Previous(BaseArgs),
-	// Sets the repeat mode to none, all songs in queue, or just one song respectively.
+	/// Mute by setting the volume to 0, or restore the volume from before. Works off what ytmdctrl remembers rather than the player's mute state.
+	MuteToggle(BaseArgs),
+	/// <position>: Seek to <position> into the song, as seconds or `mm:ss`. Refuses to in a live stream unless --force is given.
+	Seek(SeekArgs),
+	/// [count]: Skip to the next song in the queue, or [count] songs ahead.
+	Next(SkipArgs),
+	/// [count]: Restart the current song or go back to the previous song in the queue, [count] times over.
+	Previous(SkipArgs),
+	/// Restart the current song from the beginning, never going to the previous one.
+	Restart(BaseArgs),
+	/// Set the repeat mode to None.
 	RepeatNone(BaseArgs),
+	/// Set the repeat mode to All songs in the queue.
 	RepeatAll(BaseArgs),
+	/// Set the repeat mode to just One song.
 	RepeatSingle(BaseArgs),
-	// Shuffles all songs in the queue.
+	/// Shuffle the queue (cannot be undone). Asks for confirmation first unless --yes is given.
 	Shuffle(BaseArgs),
-	// Jump to a specific song in the queue.
-	Jumpto(SetFloatArgs),
-	// Toggles liking the current song.
+	/// <index>: Jump to <index> in the queue.
+	Jumpto(JumptoArgs),
+	/// Toggle the liked status of the current song.
 	Like(BaseArgs),
-	// Toggles disliking the current song.
+	/// Toggle the disliked status of the current song.
 	Dislike(BaseArgs),
-	// Change current song to first parameter and/or start playing the playlist specified by the second.
-	// If a playlist is specified, the song must be None or on the playlist or the player will misbehave.
+	/// Toggle the liked status of the current song, then skip to the next one.
+	LikeNext(BaseArgs),
+	/// Toggle the disliked status of the current song, then skip to the next one.
+	DislikeNext(BaseArgs),
+	/// Print whether the current song is liked. Exits 0 if liked, 1 if neither, 2 if disliked and 11 if unknown or nothing is playing.
+	IsLiked(BaseArgs),
+	/// --video <video> and/or --playlist <playlist>: Change playback to the song and/or playlist. A song given with a playlist must be on it, or the player will misbehave. --play starts playback once it has loaded.
 	Open(VideoChangeRequestArgs),
+	/// <start> <end>: Repeat the part of the song between two timestamps until interrupted.
+	Loop(LoopArgs),
+	/// Swap the current song between its audio and video versions, keeping the playback position.
+	SwitchVersion(BaseArgs),
+	/// <json>: Post <json> to YTMD as a command, for commands ytmdctrl doesn't know about yet.
+	Raw(RawArgs),
+	/// <path>: Fetch <path> under the companion API root from YTMD and print the response, for endpoints ytmdctrl doesn't know about yet.
+	Get(GetArgs),
+	/// Keep an authorized connection open and run commands given --via-daemon through it, rather than each starting from scratch.
+	Daemon(BaseArgs),
+	/// <script>: Run each line of <script> as a command, in order, against the same server. Each line can have its own --delay. Stops at the first line that fails unless --continue-on-error is given.
+	Run(RunArgs),
 }
 impl Command {
 	fn get_body(&self) -> String {
 		match self {
 			Command::State(_)
-			| Command::Playlists(_) => String::new(),
+			| Command::Playlists(_)
+			| Command::Remaining(_)
+			| Command::IsLiked(_)
+			| Command::TrackInfo(_)
+			| Command::Watch(_)
+			| Command::WaitTrackEnd(_)
+			| Command::Loop(_)
+			| Command::Run(_)
+			| Command::Get(_)
+			| Command::LikeNext(_)
+			| Command::DislikeNext(_)
+			| Command::Daemon(_)
+			| Command::MuteToggle(_)
+			| Command::SwitchVersion(_) => String::new(),
 			Command::PlayPause(_)      => String::from(r#"{"command":"playPause"}"#),
 			Command::Play(_)           => String::from(r#"{"command":"play"}"#),
 			Command::Pause(_)          => String::from(r#"{"command":"pause"}"#),
+			Command::EnsurePlaying(_)  => String::from(r#"{"command":"play"}"#),
+			Command::EnsurePaused(_)   => String::from(r#"{"command":"pause"}"#),
 			Command::VolumeUp(_)       => String::from(r#"{"command":"volumeUp"}"#),
 			Command::VolumeDown(_)     => String::from(r#"{"command":"volumeDown"}"#),
 			Command::Volume(SetFloatArgs { target, .. }) => format!    (r#"{{"command":"setVolume", "data": {}}}"#, target),
 			Command::Mute(_)           => String::from(r#"{"command":"mute"}"#),
 			Command::Unmute(_)         => String::from(r#"{"command":"unmute"}"#),
-			Command::Seek(SetFloatArgs { target, .. })    => format!    (r#"{{"command":"seekTo", "data": {}}}"#, target),
+			Command::Seek(SeekArgs { target, .. })        => format!    (r#"{{"command":"seekTo", "data": {}}}"#, target.0),
 			Command::Next(_)           => String::from(r#"{"command":"next"}"#),
 			Command::Previous(_)       => String::from(r#"{"command":"previous"}"#),
+			Command::Restart(_)        => String::from(r#"{"command":"seekTo", "data": 0}"#),
 			Command::RepeatNone(_)     => String::from(r#"{"command":"repeatMode", "data": 0}"#),
 			Command::RepeatAll(_)      => String::from(r#"{"command":"repeatMode", "data": 1}"#),
 			Command::RepeatSingle(_)   => String::from(r#"{"command":"repeatMode", "data": 2}"#),
 			Command::Shuffle(_)        => String::from(r#"{"command":"shuffle"}"#),
-			Command::Jumpto(SetFloatArgs { target, .. }) => format!    (r#"{{"command":"playQueueIndex", "data": {}}}"#, target),
+			Command::Jumpto(JumptoArgs { target, .. }) => format!    (r#"{{"command":"playQueueIndex", "data": {}}}"#, target),
 			Command::Like(_)           => String::from(r#"{"command":"toggleLike"}"#),
 			Command::Dislike(_)        => String::from(r#"{"command":"toggleDislike"}"#),
-			Command::Open(VideoChangeRequestArgs{ video, playlist, .. }) => {
-				let video = video.as_ref().map(|s| String::from("\"") + s + "\"").unwrap_or(String::from("null"));
-				let playlist = playlist.as_ref().map(|s| String::from("\"") + s + "\"").unwrap_or(String::from("null"));
-			 	format!(
-					r#"{{"command":"changeVideo", "data": {{ "videoId": {}, "playlistId": {} }} }}"#,
-					video,
-					playlist
-				)
-			},
+			Command::Open(VideoChangeRequestArgs{ video, playlist, .. }) => change_video_body(video.as_deref(), playlist.as_deref()),
+			Command::Raw(RawArgs { body, .. }) => body.clone(),
 		}
 		/*
 		format!(r#"{{"command":"{}", "data":{data}}}"#, <<command>>, <<data>>
 		 */
 	}	
-	fn get_path(&self) -> Option<&'static str> {
+	fn get_path(&self) -> Option<&str> {
 		Some(match self {
 			Command::State(_) => "state",
 			Command::Playlists(_) => "playlists",
+			Command::Get(GetArgs { path, .. }) => path.trim_start_matches('/'),
 			_ => return None,
 		})
 	}
 	fn is_get_request(&self) -> bool {
-		match self {
-			Command::State(_)
-			| Command::Playlists(_) => true,
-			_ => false,
-		}
+		matches!(self, Command::State(_) | Command::Playlists(_) | Command::Get(_))
 	}
 
-	fn get_delay(&self) -> Option<&str> {
+	// The arguments of the commands that take none of their own.
+	fn base_args(&self) -> Option<&BaseArgs> {
 		match self {
 			Command::State(base_args)
+			| Command::Remaining(base_args)
+			| Command::IsLiked(base_args)
+			| Command::TrackInfo(base_args)
 			| Command::Playlists(base_args)
 			| Command::PlayPause(base_args)
 			| Command::Play(base_args)
 			| Command::Pause(base_args)
-			| Command::VolumeUp(base_args)
-			| Command::VolumeDown(base_args)
+			| Command::EnsurePlaying(base_args)
+			| Command::EnsurePaused(base_args)
 			| Command::Mute(base_args)
 			| Command::Unmute(base_args)
-			| Command::Next(base_args)
-			| Command::Previous(base_args)
+			| Command::MuteToggle(base_args)
+			| Command::Restart(base_args)
 			| Command::RepeatNone(base_args)
 			| Command::RepeatAll(base_args)
 			| Command::RepeatSingle(base_args)
 			| Command::Shuffle(base_args)
 			| Command::Like(base_args)
-			| Command::Dislike(base_args) => base_args.delay.as_deref(),
-			Command::Volume(set_float_args)
-			| Command::Seek(set_float_args)
-			| Command::Jumpto(set_float_args) => set_float_args.delay.as_deref(),
-			Command::Open(video_change_request_args) => video_change_request_args.delay.as_deref(),
+			| Command::Dislike(base_args)
+			| Command::LikeNext(base_args)
+			| Command::DislikeNext(base_args)
+			| Command::Daemon(base_args)
+			| Command::SwitchVersion(base_args) => Some(base_args),
+			_ => None,
 		}
 	}
-	fn get_server_addr(&self) -> &str {
+
+	// The options shared by every command's arguments.
+	fn common_args(&self) -> &dyn CommonArgs {
 		match self {
 			Command::State(base_args)
+			| Command::Remaining(base_args)
+			| Command::IsLiked(base_args)
+			| Command::TrackInfo(base_args)
 			| Command::Playlists(base_args)
 			| Command::PlayPause(base_args)
 			| Command::Play(base_args)
 			| Command::Pause(base_args)
-			| Command::VolumeUp(base_args)
-			| Command::VolumeDown(base_args)
+			| Command::EnsurePlaying(base_args)
+			| Command::EnsurePaused(base_args)
 			| Command::Mute(base_args)
 			| Command::Unmute(base_args)
-			| Command::Next(base_args)
-			| Command::Previous(base_args)
+			| Command::MuteToggle(base_args)
+			| Command::Restart(base_args)
 			| Command::RepeatNone(base_args)
 			| Command::RepeatAll(base_args)
 			| Command::RepeatSingle(base_args)
 			| Command::Shuffle(base_args)
 			| Command::Like(base_args)
-			| Command::Dislike(base_args) => &*base_args.server_addr,
-			Command::Volume(set_float_args)
-			| Command::Seek(set_float_args)
-			| Command::Jumpto(set_float_args) => &*set_float_args.server_addr,
-			Command::Open(video_change_request_args) => &*video_change_request_args.server_addr,
+			| Command::Dislike(base_args)
+			| Command::LikeNext(base_args)
+			| Command::DislikeNext(base_args)
+			| Command::Daemon(base_args)
+			| Command::SwitchVersion(base_args) => base_args,
+			Command::VolumeUp(volume_up_args) => volume_up_args,
+			Command::VolumeDown(volume_down_args) => volume_down_args,
+			Command::Volume(set_float_args) => set_float_args,
+			Command::Jumpto(jumpto_args) => jumpto_args,
+			Command::Seek(seek_args) => seek_args,
+			Command::Next(skip_args)
+			| Command::Previous(skip_args) => skip_args,
+			Command::Open(video_change_request_args) => video_change_request_args,
+			Command::Watch(watch_args) => watch_args,
+			Command::WaitTrackEnd(wait_args) => wait_args,
+			Command::Loop(loop_args) => loop_args,
+			Command::Run(run_args) => run_args,
+			Command::Raw(raw_args) => raw_args,
+			Command::Get(get_args) => get_args,
+		}
+	}
+	fn get_delay(&self) -> Option<&str> {
+		self.common_args().delay()
+	}
+	fn get_server_addr(&self) -> &str {
+		self.common_args().server_addr()
+	}
+	// What the server's token is stored under: its address, with the port unless it's the default one,
+	// so that companion servers sharing a host each keep their own token.
+	fn get_token_key(&self) -> String {
+		match self.common_args().port() {
+			DEFAULT_PORT => self.get_server_addr().to_string(),
+			port => format!("{}:{port}", self.common_args().url_host()),
+		}
+	}
+	// `http://<server>:<port>`, without a trailing slash.
+	fn get_base_url(&self) -> String {
+		format!("http://{}:{}", self.common_args().url_host(), self.common_args().port())
+	}
+	// The URL of `path` in the companion server API, such as `state` or `auth/request`.
+	fn get_api_url(&self, path: &str) -> String {
+		format!("{}/api/{API_VERSION}/{path}", self.get_base_url())
+	}
+
+	// JSON output for this command, indented unless `--no-pretty` was given.
+	fn to_json(&self, value: &impl serde::Serialize) -> String {
+		if self.common_args().pretty() {
+			serde_json::to_string_pretty(value).unwrap()
+		} else {
+			serde_json::to_string(value).unwrap()
 		}
+	}
 
+	// The mute state `--confirm` expects to see after the command, if confirmation was requested.
+	fn confirm_muted(&self) -> Option<bool> {
+		match self {
+			Command::Mute(base_args) if base_args.confirm => Some(true),
+			Command::Unmute(base_args) if base_args.confirm => Some(false),
+			_ => None,
+		}
+	}
+
+	// Whether the command can run within another: as a line of a `run` script or on behalf of `--via-daemon`.
+	// Those can't nest, and the daemon only reports back an exit code, so commands that print are run directly instead.
+	fn runs_in_process(&self) -> bool {
+		!matches!(self, Command::Run(_) | Command::Daemon(_))
+	}
+	fn runs_in_daemon(&self) -> bool {
+		!matches!(self,
+			Command::State(_)
+			| Command::Remaining(_)
+			| Command::Playlists(_)
+			| Command::IsLiked(_)
+			| Command::TrackInfo(_)
+			| Command::Watch(_)
+			| Command::WaitTrackEnd(_)
+			| Command::Get(_)
+			| Command::Loop(_)
+		) && self.runs_in_process()
+	}
+
+	fn is_quiet(&self) -> bool {
+		match self {
+			Command::IsLiked(base_args) => base_args.quiet,
+			_ => false,
+		}
 	}
 
 	// TODO: make output different for script mode and human mode
-	#[allow(dead_code)]
 	fn is_script_mode(&self) -> bool {
 		match self {
 			Command::State(base_args)
-			| Command::Playlists(base_args) => base_args.script_mode,
+			| Command::Remaining(base_args)
+			| Command::Playlists(base_args)
+			| Command::Shuffle(base_args)
+			| Command::IsLiked(base_args) => base_args.script_mode,
 			Command::PlayPause(_)
 			| Command::Play(_)
 			| Command::Pause(_)
+			| Command::EnsurePlaying(_)
+			| Command::EnsurePaused(_)
 			| Command::VolumeUp(_)
 			| Command::VolumeDown(_)
 			| Command::Mute(_)
 			| Command::Unmute(_)
+			| Command::MuteToggle(_)
 			| Command::Next(_)
 			| Command::Previous(_)
+			| Command::Restart(_)
 			| Command::RepeatNone(_)
 			| Command::RepeatAll(_)
 			| Command::RepeatSingle(_)
-			| Command::Shuffle(_)
 			| Command::Like(_)
 			| Command::Dislike(_)
+			| Command::LikeNext(_)
+			| Command::DislikeNext(_)
+			| Command::Daemon(_)
+			| Command::TrackInfo(_)
+			| Command::SwitchVersion(_)
 			| Command::Volume(_)
 			| Command::Seek(_)
 			| Command::Jumpto(_)
-			| Command::Open(_) => false,
+			| Command::Open(_)
+			| Command::Watch(_)
+			| Command::WaitTrackEnd(_)
+			| Command::Run(_)
+			| Command::Raw(_)
+			| Command::Get(_)
+			| Command::Loop(_) => false,
 		}
 
 	}
 
 }
 	
+fn change_video_body(video: Option<&str>, playlist: Option<&str>) -> String {
+	let video = video.map(|s| String::from("\"") + s + "\"").unwrap_or(String::from("null"));
+	let playlist = playlist.map(|s| String::from("\"") + s + "\"").unwrap_or(String::from("null"));
+	format!(
+		r#"{{"command":"changeVideo", "data": {{ "videoId": {}, "playlistId": {} }} }}"#,
+		video,
+		playlist
+	)
+}
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct PlaylistEntry<'a> {
 	id: &'a str,
-	title: &'a str,
+	// owned, since a title with escapes in the JSON (quotes, tabs) can't be borrowed from it as-is
+	title: String,
+}
+
+// `$XDG_CONFIG_HOME/ytmdctrl`, falling back to `~/.config/ytmdctrl`.
+fn get_config_dir() -> &'static Path {
+	static PATH: OnceCell<PathBuf> = OnceCell::new();
+	PATH.get_or_init(|| {
+		let config_home = std::env::var_os("XDG_CONFIG_HOME")
+			.map(PathBuf::from)
+			// the spec says relative paths are invalid and should be ignored
+			.filter(|p| p.is_absolute())
+			.unwrap_or_else(|| env_home::env_home_dir().expect("Unable to locate home directory").join(".config"));
+		config_home.join("ytmdctrl")
+	})
 }
 
 fn get_token_store_path() -> &'static Path {
 	static PATH: OnceCell<PathBuf> = OnceCell::new();
-	PATH.get_or_init(|| env_home::env_home_dir().expect("Unable to locate home directory").join(".config/ytmdctrl.tkn"))
+	PATH.get_or_init(|| {
+		let path = get_config_dir().join("ytmdctrl.tkn");
+		// Older versions kept the store directly in `~/.config`; move it over so existing tokens keep working
+		if !path.exists() {
+			if let Some(legacy) = env_home::env_home_dir().map(|home| home.join(".config/ytmdctrl.tkn")) {
+				if legacy.is_file() && std::fs::create_dir_all(get_config_dir()).is_ok() {
+					if let Err(e) = std::fs::rename(&legacy, &path) {
+						eprintln!("Failed to move token store from {} to {}: {e}", legacy.display(), path.display());
+						return legacy;
+					}
+				}
+			}
+		}
+		path
+	})
 }
 
-fn read_token_store() -> Option<HashMap<String, String>> {
-	if let Ok(tknstrs) = std::fs::read_to_string(get_token_store_path()) {
-		serde_json::from_str::<HashMap<String, String>>(&tknstrs).ok()
-	} else {
-		None
+fn get_config() -> &'static Config {
+	static CONFIG: OnceCell<Config> = OnceCell::new();
+	CONFIG.get_or_init(|| {
+		let path = get_config_dir().join("config.toml");
+		Config::load(&path).unwrap_or_else(|e| {
+			eprintln!("Unable to load {}: {e}", path.display());
+			std::process::exit(ERR_INVALID_ARGS);
+		})
+	})
+}
+
+fn get_daemon_socket_path() -> &'static Path {
+	static PATH: OnceCell<PathBuf> = OnceCell::new();
+	PATH.get_or_init(|| get_config_dir().join("daemon.sock"))
+}
+
+// The volume each server was at before `mute-toggle` muted it (server -> volume).
+fn get_muted_volumes_path() -> &'static Path {
+	static PATH: OnceCell<PathBuf> = OnceCell::new();
+	PATH.get_or_init(|| get_config_dir().join("muted-volumes.json"))
+}
+
+// `arg` only takes an option's value as the next argument, so `--server=host` is split into `--server host` first.
+fn split_long_options(args: &mut Vec<String>) {
+	*args = std::mem::take(args)
+		.into_iter()
+		.flat_map(|arg| match arg.strip_prefix("--").and_then(|rest| rest.split_once('=')) {
+			Some((name, value)) if !name.is_empty() => vec![format!("--{name}"), value.to_string()],
+			_ => vec![arg],
+		})
+		.collect();
+}
+
+// The command line as it can be written to the `--log`: `--header` values, which can hold credentials, are left out.
+fn redact_headers(args: &[String]) -> Vec<String> {
+	let mut redacted = Vec::with_capacity(args.len());
+	let mut header_next = false;
+	for arg in args {
+		if std::mem::take(&mut header_next) {
+			let name = arg.split_once(':').map_or(&**arg, |(name, _)| name);
+			redacted.push(format!("{name}: <redacted>"));
+			continue;
+		}
+		header_next = arg == "--header";
+		redacted.push(arg.clone());
 	}
+	redacted
 }
 
-const USEFUL_HELP:  &'static str = "\
+// Puts the options `[commands.<command>]` in `config.toml` gives right after the command, so the ones actually written,
+// coming later, take precedence. Returns the config section used, if there was one.
+fn apply_command_defaults(args: &mut Vec<String>) -> Option<String> {
+	let idx = args.iter().position(|a| !a.starts_with('-'))?;
+	let defaults = get_config().command_defaults(&args[idx]);
+	if defaults.is_empty() {
+		return None;
+	}
+	args.splice(idx + 1..idx + 1, defaults.iter().cloned());
+	Some(format!("[commands.{}]", args[idx]))
+}
+
+// `--delay until 23:30` arrives as two arguments, but is a single delay
+fn join_delay_until(args: &mut Vec<String>) {
+	if let Some(idx) = args.iter().position(|a| a == "-p" || a == "--delay") {
+		if args.get(idx + 1).is_some_and(|a| a == "until") && idx + 2 < args.len() {
+			let time = args.remove(idx + 2);
+			args[idx + 1] = format!("until {time}");
+		}
+	}
+}
+
+// Built from the doc comments on `Command` and `BaseArgs`, so new commands and options show up on their own.
+fn useful_help() -> String {
+	let options = BaseArgs::HELP.find("OPTIONS:").map_or(BaseArgs::HELP, |idx| &BaseArgs::HELP[idx..]);
+	format!("\
 Control the Youtube Music Desktop Player from the CLI or scripts.
-Options:
-	--delay,  -p     Delays execution by a certain amount of time.
-	--server, -s     Sets the ip of the server to connect to.
-	                 Default is `localhost`.
-	--script_mode    Adjusts output of 'get' commands to be better
-	                 for scripts. Currently has no effect.
-Commands:
-	state:           Current player state.
-	playlists:       List all playlists in the user's account.
-	play-pause:      Toggle playback.
-	play:            Resume/Start playback.
-	pause:           Pause playback.
-	volume-up:       Increase volume.
-	volume-down:     Decrease volume.
-	volume <target>: Set volume to <target>%.
-	mute:            Mutes playback.
-	unmute:          Unmutes playback.
-	seek <seconds>:  Seeks to <seconds> into the song.
-	next:            Skip to next song in the queue.
-	previous:        Restart the current song or go back to the previous song in the queue.
-	repeat-none:     Sets the repeat mode to None.
-	repeat-all:      Sets the repeat mode to All.
-	repeat-single:   Sets the repeat mode to One.
-	shuffle:         Shuffles the queue (cannot be undone).
-	jumpto <index>:  Jumps to a specific <index> in the queue.
-	like:            Toggles the liked status of the song.
-	dislike:         Toggles the disliked status of the song.
-	open
-		[--video <video>]
-		[--playlist <playlist>]: 
-	                 Changes playback to the specified song or playlist. One or both must be specified.
-";
+USAGE: ytmdctrl [COMMAND] [OPTIONS]
+Run `ytmdctrl <COMMAND> --help` for the arguments and options of a single command.
+
+{}
+{options}", Command::HELP)
+}
 
 
 #[tokio::main]
 async fn main() {
 	let mut args: Vec<String> = std::env::args().skip(1).collect();
 	// handle standard help command syntax, arg's help command is nonstandard
-	if args.iter().find(|a| &**a == "-h" || &**a == "--help").is_some() {
-		std::println!("{}", USEFUL_HELP);
+	let wants_help = args.iter().any(|a| a == "-h" || a == "--help");
+	if wants_help && args.iter().all(|s| s.starts_with('-')) {
+		std::println!("{}", useful_help());
 		return;
 	}
+	split_long_options(&mut args);
+	join_delay_until(&mut args);
 	// if only flags (or nothing) are specified with no command, assume the command is play-pause
 	if args.iter().find(|s| !s.starts_with('-')).is_none() {
 		args.insert(0, "play-pause".to_owned());
 	}
-	let command = if let Ok(cmd) = Command::from_args(args.iter().map(|s| &**s)) {
-		cmd
-	} else {
-		// print the help message on invalid commands rather than an unhelpful error
-		match &*(args.iter().find(|s| !s.starts_with('-')).unwrap().to_lowercase()) {
-			"volume" => std::eprintln!("`volume` requires a percentage to set volume to between 0 and 100\n"),
-			"seek" => std::eprintln!("`seek` requires a time to seek to in seconds\n"),
-			"jumpto" => std::eprintln!("`jumpto` requires an integer index in the queue to jump to\n"),
-			arg => std::eprintln!("Invalid command `{arg}`\n"),
-		}
-		
-		std::println!("{}", USEFUL_HELP);
-		std::process::exit(ERR_INVALID_ARGS);
+	// the daemon applies the defaults itself, so it's handed the command as it was given
+	let given_args = args.clone();
+	let defaults = apply_command_defaults(&mut args);
+	let defaults_note = || if let Some(section) = &defaults {
+		eprintln!("(including the defaults from {section} in config.toml)");
 	};
-	if let Command::Open(VideoChangeRequestArgs { video: None, playlist: None, ..}) = command {
-		eprintln!("`open` requires either --video or --playlist to be specified");
-		return;
+	let command = match Command::from_args(args.iter().map(|s| &**s)) {
+		Ok(cmd) => cmd,
+		// `<command> --help`
+		Err(ParseKind::Sub(name, ParseError::HelpRequested(help))) => {
+			std::println!("ytmdctrl {name}\n{}", help.trim_start());
+			return;
+		},
+		// `help`
+		Err(ParseKind::Top(ParseError::HelpRequested(_))) => {
+			std::println!("{}", useful_help());
+			return;
+		},
+		Err(_) if wants_help => {
+			std::println!("{}", useful_help());
+			return;
+		},
+		Err(ParseKind::Sub(_, ParseError::InvalidFlagValue(name, value))) => {
+			// `name` is the field name, so show the flag as it was written
+			let flag = args.windows(2).find(|w| w[1] == value).map_or(name, |w| &*w[0]);
+			eprintln!("Invalid value `{value}` for `{flag}`");
+			defaults_note();
+			std::process::exit(ERR_INVALID_ARGS);
+		},
+		Err(ParseKind::Sub(name, ParseError::UnknownFlag(flag))) => {
+			// `flag` has lost a dash, so show it as it was written
+			let flag = args.iter().find(|a| a.starts_with('-') && a.trim_start_matches('-') == flag.trim_start_matches('-')).map_or(flag, |a| &**a);
+			eprintln!("`{name}` has no option `{flag}`; see `ytmdctrl {name} --help` for the ones it has");
+			defaults_note();
+			std::process::exit(ERR_INVALID_ARGS);
+		},
+		Err(_) => {
+			// print the help message on invalid commands rather than an unhelpful error
+			match &*(args.iter().find(|s| !s.starts_with('-')).unwrap().to_lowercase()) {
+				"volume" => std::eprintln!("`volume` requires a percentage to set volume to between 0 and 100\n"),
+				"seek" => std::eprintln!("`seek` requires a position to seek to, such as `90` or `1:30`\n"),
+				"jumpto" => std::eprintln!("`jumpto` requires an integer index in the queue to jump to\n"),
+				arg @ ("next" | "previous") => std::eprintln!("`{arg}` takes an optional number of songs to skip, such as `3`\n"),
+				"loop" => std::eprintln!("`loop` requires a start and end time such as `1:10 1:35`\n"),
+				"get" => std::eprintln!("`get` requires a path under /api/{API_VERSION}/ such as `playlists`\n"),
+				"raw" => std::eprintln!("`raw` requires a JSON command body such as `{{\"command\":\"play\"}}`\n"),
+				arg => std::eprintln!("Invalid command `{arg}`\n"),
+			}
+			defaults_note();
+			
+			std::println!("{}", useful_help());
+			std::process::exit(ERR_INVALID_ARGS);
+		},
+	};
+	if let Some(path) = command.common_args().log() {
+		let server = format!("{}:{}", command.common_args().url_host(), command.common_args().port());
+		let _ = RUN_LOG.set(RunLog { path: path.to_path_buf(), command: redact_headers(&given_args).join(" "), server });
+	}
+	if let Err(e) = check_args(&command) {
+		eprintln!("{e}");
+		exit_process(ERR_INVALID_ARGS);
+	}
+	if command.common_args().via_daemon() && command.runs_in_daemon() {
+		let server = format!("{}:{}", command.common_args().url_host(), command.common_args().port());
+		// already validated
+		let delay = command.get_delay().map(|delay| timefmt::parse_delay(delay).unwrap());
+		let mut args = given_args.clone();
+		// the daemon has no one to ask, so it's asked here and handed the answer
+		if let Command::Shuffle(BaseArgs { yes: false, .. }) = command {
+			if let Err(stop) = confirm_shuffle(&command) {
+				exit_process(stop.code());
+			}
+			args.push(String::from("--yes"));
+		}
+		if let Some(code) = send_to_daemon(&args, &server, delay).await {
+			exit_process(code);
+		}
 	}
-	let client = reqwest::Client::new();
+	let client = build_client(&command).await;
+	if let Some(timeout) = command.common_args().wait_connection() {
+		// already validated
+		wait_for_connection(&client, &command, timefmt::parse_delay(timeout).unwrap()).await;
+	}
+	let store = FileTokenStore::new(get_token_store_path().to_path_buf());
+	match run_authorized(command, client, &store).await {
+		Ok(outcome) => log_run(0, outcome),
+		Err(stop) => exit_process(stop.code()),
+	}
+}
+
+// Runs `command` with the token `store` holds for its server, asking YTMD for one first if there's none.
+// A token YTMD rejects is deleted from `store`, and a new one is kept unless YTMD rejects it straight away.
+// Returns what to log the run as, if there's more to say than its exit code.
+async fn run_authorized(command: Command, client: reqwest::Client, store: &dyn TokenStore) -> Outcome<Option<&'static str>> {
+	let ip = command.get_token_key();
 	// Check for token in store
-	let mut store = read_token_store().unwrap_or_else(|| {
-		std::fs::create_dir_all(get_token_store_path().parent().unwrap()).unwrap();
-		let tkn_file = std::fs::File::create(get_token_store_path()).unwrap();
-		tkn_file.set_permissions(owner_only()).unwrap();
-		HashMap::new()
-	});
-	if let Some(token) = read_token_store().and_then(|mut tkstr| tkstr.remove(command.get_server_addr())) {
-		main_logic(command, client, &token).await;
-		return
+	if let Some(token) = store.get(&ip) {
+		return match main_logic(command, client, &token).await {
+			Err(Stop::TokenRejected) => {
+				if let Err(e) = store.remove(&ip) {
+					eprintln!("Unable to delete the token from {}: {e}", store.location());
+				}
+				Ok(None)
+			},
+			result => result.map(|()| None),
+		};
 	}
-	let ip = command.get_server_addr();
 	// No token stored, we need to obtain one
+	// Point out the servers we do know about in case the address was mistyped
+	let known = store.servers();
+	if !known.is_empty() {
+		eprintln!("No token stored for `{ip}`; tokens are stored for: {}", known.join(", "));
+	}
+	let app_id = command.common_args().app_id();
+	if app_id != DEFAULT_APP_ID {
+		eprintln!("Note: tokens are tied to the app id they were requested with; later runs need the same --app-id,");
+		eprintln!("and authorizing under a different one will invalidate this token");
+	}
 	// Get the code from YTMD for requesting authorization
-	let code_response = client.post(format!("http://{ip}:9863/api/v1/auth/requestcode")).body(r#"{
-		"appId": "ytmdctrl", 
-		"appName": "Seta's YTMD CLI", 
+	let code_response = client.post(command.get_api_url("auth/requestcode")).body(serde_json::json!({
+		"appId": app_id,
+		"appName": command.common_args().app_name(),
 		"appVersion": "0.0.2"
-	}"#).header("content-type", "application/json").send().await.unwrap();
+	}).to_string()).header("content-type", "application/json").send().await.map_err(|e| report_send_error(&command, e))?;
+	check_is_ytmd(&command, &code_response)?;
 	if code_response.status() != StatusCode::OK {
-		eprintln!("Failed to get code for token request; Enable companion authorization in YTMD settings and rerun command");
-		return;
+		let status = code_response.status();
+		let error = code_response.text().await.ok()
+			.and_then(|body| serde_json::from_str::<Value>(&body).ok())
+			.and_then(|body| body["error"].as_str().map(String::from));
+		// the companion server itself is up, it's just not letting new apps in
+		if status == StatusCode::FORBIDDEN || error.as_deref() == Some("AUTHORIZATION_DISABLED") {
+			eprintln!("YTMD's companion server is running, but companion authorization is disabled");
+			eprintln!("Enable \"Allow companion authorization\" in YTMD's settings (Settings > Integrations) and rerun the command");
+		} else {
+			let error = error.map_or(String::new(), |e| format!(" ({e})"));
+			eprintln!("Failed to get code for token request: YTMD answered {status}{error}");
+			if status == StatusCode::NOT_FOUND {
+				warn_on_api_mismatch(&client, &command).await;
+			}
+		}
+		return Err(Stop::Exit(ERR_COMMAND_FAILED));
 	}
 	let code: String = serde_json::from_str::<Value>(
 		&code_response.text().await.unwrap()
@@ -352,102 +1023,394 @@ async fn main() {
 	eprintln!("authorization code is {code}");
 	// Use the code to request a token; user will need to have enabled companion authorization and approve 
 	// the authorization request
-	let token_response = client.post(format!("http://{ip}:9863/api/v1/auth/request")).body(format!(r#"{{
-		"appId": "ytmdctrl",
-		"code": "{code}"
-	}}"#)).header("content-type", "application/json").send().await.unwrap();
+	let token_response = client.post(command.get_api_url("auth/request")).body(serde_json::json!({
+		"appId": app_id,
+		"code": code
+	}).to_string()).header("content-type", "application/json").send().await.map_err(|e| report_send_error(&command, e))?;
 	if token_response.status() != StatusCode::OK {
 		eprintln!("Failed to get token; Companion Authorization Request Denied");
-		return;
+		return Ok(Some("authorization denied"));
 	}
 	let token: String = serde_json::from_str::<Value>(
 		&token_response.text().await.unwrap()
 	).unwrap()["token"].as_str().unwrap().to_string();
 
-	store.insert(ip.to_string(), token.clone());
-	let mut tkn_file = std::fs::File::create(get_token_store_path()).unwrap();
-	if main_logic(command, client, &token).await {
-		tkn_file.write(&serde_json::to_vec(&store).unwrap()).unwrap();
+	let result = main_logic(command, client, &token).await;
+	if let Err(Stop::TokenRejected) = result {
+		return Ok(None);
+	}
+	if let Err(e) = store.set(&ip, &token) {
+		eprintln!("Unable to save the token to {}: {e}", store.location());
+	}
+	result.map(|()| None)
+}
+
+
+// Set while `--delay` is being waited out, when SIGINT/SIGTERM cancel the command rather than interrupting it.
+static DELAYING: AtomicBool = AtomicBool::new(false);
+
+async fn wait_out_delay(duration: std::time::Duration) {
+	static SIGNAL_HANDLER: std::sync::Once = std::sync::Once::new();
+	SIGNAL_HANDLER.call_once(|| {
+		use tokio::signal::unix::{signal, SignalKind};
+		let mut interrupt = signal(SignalKind::interrupt()).unwrap();
+		let mut terminate = signal(SignalKind::terminate()).unwrap();
+		// once tokio is listening for a signal its default action no longer applies,
+		// so outside of a delay this stands in for it and exits the way the signal would have
+		tokio::spawn(async move {
+			let code = tokio::select! {
+				_ = interrupt.recv() => 130,
+				_ = terminate.recv() => 143,
+			};
+			if DELAYING.load(Ordering::Relaxed) {
+				eprintln!("cancelled");
+				log_run(0, Some("cancelled during the delay"));
+				std::process::exit(0);
+			}
+			exit_process(code);
+		});
+	});
+	DELAYING.store(true, Ordering::Relaxed);
+	tokio::time::sleep(duration).await;
+	DELAYING.store(false, Ordering::Relaxed);
+}
+
+// Checks what parsing the arguments can't, before anything is sent.
+fn check_args(command: &Command) -> Result<(), String> {
+	if let Some(delay) = command.get_delay() {
+		if timefmt::parse_delay(delay).is_none() {
+			return Err(format!("Invalid delay `{delay}`; expected seconds, a duration like `30s` or `1h30m`, or `until HH:MM`"));
+		}
+	}
+	if let Command::Loop(LoopArgs { start, end, .. }) = command {
+		if end.0 <= start.0 {
+			return Err(String::from("`loop` requires the end time to be after the start time"));
+		}
+	}
+	if let Command::Next(SkipArgs { count: Some(0), .. }) | Command::Previous(SkipArgs { count: Some(0), .. }) = command {
+		return Err(String::from("The number of songs to skip must be at least 1"));
+	}
+	if let Command::Open(VideoChangeRequestArgs { video: None, playlist: None, ..}) = command {
+		return Err(String::from("`open` requires either --video or --playlist to be specified"));
+	}
+	if let Command::Raw(RawArgs { body, .. }) = command {
+		if let Err(e) = serde_json::from_str::<Value>(body) {
+			return Err(format!("`raw` requires a valid JSON body: {e}"));
+		}
+	}
+	if let Command::Get(GetArgs { path, .. }) = command {
+		// the path is appended to the server's url, so keep it to plain path segments that can't lead anywhere else
+		let path = path.trim_start_matches('/');
+		let plain = path.split('/').all(|segment| {
+			!segment.is_empty() && segment != "." && segment != ".."
+				&& segment.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+		});
+		if !plain {
+			return Err(format!("Invalid path `{path}`; expected something like `playlists` or `playlists/<id>`"));
+		}
+	}
+	if let Command::WaitTrackEnd(WaitArgs { poll_interval, timeout, .. }) = command {
+		for (flag, value) in [("--poll-interval", poll_interval), ("--timeout", timeout)] {
+			if let Some(value) = value {
+				if timefmt::parse_delay(value).is_none_or(|d| d.is_zero()) || value.starts_with("until") {
+					return Err(format!("Invalid duration `{value}` for `{flag}`"));
+				}
+			}
+		}
+	}
+	if let Some(timeout) = command.common_args().wait_connection() {
+		if timefmt::parse_delay(timeout).is_none_or(|d| d.is_zero()) || timeout.starts_with("until") {
+			return Err(format!("Invalid duration `{timeout}` for `--wait-connection`"));
+		}
+	}
+	if let Some(base_args) = command.base_args() {
+		check_base_options(command, base_args)?;
+	}
+	if let Command::State(BaseArgs { art_width: Some(cols), .. }) = command {
+		if cfg!(not(feature = "image")) {
+			return Err(String::from("--art-width needs ytmdctrl to be built with the `image` feature"));
+		}
+		if !(1..=500).contains(cols) {
+			return Err(format!("Invalid width `{cols}` for `--art-width`; expected between 1 and 500 columns"));
+		}
+	}
+	if let Command::State(BaseArgs { no_automix: true, automix_only: true, .. }) = command {
+		return Err(String::from("--no-automix and --automix-only cannot be used together"));
+	}
+	if let Command::VolumeUp(VolumeUpArgs { max_volume: Some(limit), .. }) | Command::VolumeDown(VolumeDownArgs { min_volume: Some(limit), .. }) = command {
+		if *limit > 100 {
+			return Err(format!("Invalid volume limit `{limit}`; expected a percentage between 0 and 100"));
+		}
+	}
+	if command.common_args().ipv4() && command.common_args().ipv6() {
+		return Err(String::from("--ipv4 and --ipv6 cannot be used together"));
+	}
+	if let Some(header) = command.common_args().headers().iter().find(|header| parse_header(header).is_none()) {
+		return Err(format!("Invalid header `{header}`; expected `Name: value`"));
+	}
+	Ok(())
+}
+
+// `BaseArgs` is shared by every command without arguments of its own, so its options reach commands they mean nothing to.
+// Those are refused rather than silently ignored.
+fn check_base_options(command: &Command, args: &BaseArgs) -> Result<(), String> {
+	let state = matches!(command, Command::State(_));
+	let options = [
+		("--confirm", args.confirm, matches!(command, Command::Mute(_) | Command::Unmute(_)), "`mute` and `unmute`"),
+		("--quiet", args.quiet, matches!(command, Command::IsLiked(_)), "`is-liked`"),
+		("--yes", args.yes, matches!(command, Command::Shuffle(_)), "`shuffle`"),
+		("--json", args.json, matches!(command, Command::TrackInfo(_)), "`track-info`"),
+		("--wait-until-playing", args.wait_until_playing, matches!(command, Command::Play(_)), "`play`"),
+		("--field", args.field.is_some(), state, "`state`"),
+		("--since", args.since.is_some(), state, "`state`"),
+		("--compact", args.compact, state, "`state`"),
+		("--no-unicode", args.no_unicode, state, "`state`"),
+		("--no-automix", args.no_automix, state, "`state`"),
+		("--automix-only", args.automix_only, state, "`state`"),
+		("--template", args.template.is_some(), state, "`state`"),
+		("--art-width", args.art_width.is_some(), state, "`state`"),
+	];
+	match options.into_iter().find(|(_, given, applies, _)| *given && !applies) {
+		Some((flag, _, _, commands)) => Err(format!("{flag} is only for {commands}")),
+		None => Ok(()),
 	}
 }
 
+// Splits a `--header` into its name and value, if both are valid.
+fn parse_header(header: &str) -> Option<(HeaderName, HeaderValue)> {
+	let (name, value) = header.split_once(':')?;
+	let name = HeaderName::from_bytes(name.trim().as_bytes()).ok()?;
+	let value = HeaderValue::from_str(value.trim()).ok()?;
+	Some((name, value))
+}
+
+// Builds the HTTP client, pinning the server to a single address family if `--ipv4`/`--ipv6` was given.
+// Otherwise both families are tried (happy eyeballs) as usual.
+async fn build_client(command: &Command) -> reqwest::Client {
+	let args = command.common_args();
+	let headers = args.headers().iter().filter_map(|header| parse_header(header)).collect();
+	let mut builder = reqwest::Client::builder()
+		.user_agent(USER_AGENT)
+		.default_headers(headers)
+		.connect_timeout(CONNECT_TIMEOUT)
+		// YTMD never redirects, so a redirect means we're not talking to YTMD
+		.redirect(reqwest::redirect::Policy::none());
+	if args.ipv4() || args.ipv6() {
+		let host = args.server_addr();
+		let family = if args.ipv6() { "IPv6" } else { "IPv4" };
+		let addr = tokio::net::lookup_host((host, args.port())).await
+			.ok()
+			.and_then(|mut addrs| addrs.find(|addr| addr.is_ipv6() == args.ipv6()));
+		let Some(addr) = addr else {
+			eprintln!("Unable to find an {family} address for `{host}`");
+			exit_process(ERR_COMMAND_FAILED);
+		};
+		builder = builder.resolve(host, addr);
+	}
+	builder.build().unwrap()
+}
 
-// Returns `true` if the token was valid. `false` means the token should not be stored.
-async fn main_logic(command: Command, client: reqwest::Client, token: &str) -> bool {
+// Runs `command`. `Stop::TokenRejected` means the token should not be stored.
+async fn main_logic(command: Command, client: reqwest::Client, token: &str) -> Outcome {
 	let token = token.trim();
 	if let Some(delay) = command.get_delay() {
-		let sleep_time = parse_duration::parse(delay).unwrap();
-		tokio::time::sleep(sleep_time).await;
+		// already validated in `main`; an `until` delay is worked out now since authorizing may have taken a while
+		let sleep_time = timefmt::parse_delay(delay).unwrap();
+		// commands handed to the daemon come with their delay already waited out
+		if !sleep_time.is_zero() {
+			wait_out_delay(sleep_time).await;
+		}
+	}
+	match command {
+		Command::SwitchVersion(_) => return switch_version(&client, &command, token).await,
+		Command::Remaining(_) => return print_remaining(&client, &command, token).await,
+		Command::IsLiked(_) => return print_like_status(&client, &command, token).await,
+		Command::TrackInfo(BaseArgs { json, .. }) => return print_track_info(&client, &command, token, json).await,
+		Command::MuteToggle(_) => return mute_toggle(&client, &command, token).await,
+		Command::EnsurePlaying(_) | Command::EnsurePaused(_) => return ensure_playback(&client, &command, token).await,
+		Command::VolumeUp(VolumeUpArgs { max_volume: Some(max), .. }) => return step_volume_within(&client, &command, token, 0, max).await,
+		Command::VolumeDown(VolumeDownArgs { min_volume: Some(min), .. }) => return step_volume_within(&client, &command, token, min, 100).await,
+		Command::Next(SkipArgs { count: Some(count), .. }) | Command::Previous(SkipArgs { count: Some(count), .. }) if count != 1 => {
+			return skip(&client, &command, token, count).await;
+		},
+		Command::LikeNext(_) => return send_in_sequence(&client, &command, token, &[r#"{"command":"toggleLike"}"#, r#"{"command":"next"}"#]).await,
+		Command::DislikeNext(_) => return send_in_sequence(&client, &command, token, &[r#"{"command":"toggleDislike"}"#, r#"{"command":"next"}"#]).await,
+		Command::Watch(WatchArgs { ref fifo, .. }) => {
+			let output = match fifo {
+				Some(path) => WatchOutput::Fifo { path: path.clone(), pipe: None },
+				None => WatchOutput::Stdout,
+			};
+			return watch(&client, &command, token, output).await;
+		},
+		Command::Loop(LoopArgs { start, end, .. }) => return ab_loop(&client, &command, token, start.0, end.0).await,
+		Command::Run(RunArgs { ref script, continue_on_error, .. }) => return run_script(&client, &command, token, script, continue_on_error).await,
+		Command::Daemon(_) => return run_daemon(&client, &command, token).await,
+		Command::WaitTrackEnd(WaitArgs { ref poll_interval, ref timeout, .. }) => {
+			// both already validated in `main`
+			let poll_interval = poll_interval.as_deref().map_or(STATE_POLL_INTERVAL, |i| timefmt::parse_delay(i).unwrap());
+			let timeout = timeout.as_deref().map(|t| timefmt::parse_delay(t).unwrap());
+			return wait_track_end(&client, &command, token, poll_interval, timeout).await;
+		},
+		_ => (),
+	}
+	if let Command::Shuffle(BaseArgs { yes: false, .. }) = command {
+		confirm_shuffle(&command)?;
+	}
+	if let Command::Seek(SeekArgs { force: false, .. }) = command {
+		check_not_live(&client, &command, token).await?;
+	}
+	if let Command::Open(VideoChangeRequestArgs { video: Some(ref video), playlist: Some(ref playlist), .. }) = command {
+		check_on_playlist(&client, &command, token, video, playlist).await?;
+	}
+	if let Command::Jumpto(JumptoArgs { target, wait_generate: true, .. }) = command {
+		let index = target as usize;
+		let loaded = wait_for_state(&client, &command, token, std::time::Duration::ZERO, QUEUE_LOAD_TIMEOUT, |state| {
+			state.player.queue.as_ref().is_none_or(|queue| !queue.is_generating || queue.items.len() > index)
+		}).await;
+		match loaded {
+			Ok(true) => (),
+			Ok(false) => eprintln!("The queue is still being generated; jumping anyway"),
+			Err(stop) => return Err(stop),
+		}
 	}
 	let response = if let Some(path) = command.get_path() {
-		client.get(format!("http://{}:9863/api/v1/{}", command.get_server_addr(), path))
+		client.get(command.get_api_url(path))
 			.header("Authorization", token)
-			.send().await.unwrap()
+			.send().await.map_err(|e| report_send_error(&command, e))?
 	} else {
-		let builder = client.post(format!("http://{}:9863/api/v1/command", command.get_server_addr()))
+		let builder = client.post(command.get_api_url("command"))
 			.header("content-type", "application/json")
 			.header("Authorization", token);
 		builder
 			.body(command.get_body())
-			.send().await.unwrap()
+			.send().await.map_err(|e| report_send_error(&command, e))?
 	};
+	dump_headers(&command, &response);
 
-	if response.status() == StatusCode::TOO_MANY_REQUESTS {
-		eprintln!("Rate limit exceeded");
-		eprintln!("Wait {} seconds before submitting another request", 
-			response.headers().get("x-ratelimit-reset").and_then(|v| v.to_str().ok()).unwrap_or("5")
-		);
-		return true;
-	} else if !response.status().is_success() {
-		eprintln!("Command sent to YTMD Failed: {response:#?}");
-		let body = response.text().await.unwrap();
-		if let Ok(parsed) = serde_json::from_str::<Value>(&body) {
-			if parsed.get("error").map_or(false, |e| e.as_str().map_or(false, |e| e == "UNAUTHORIZED")) {
-				// UNAUTHORIZED means our current token is invalid
-				eprintln!("Server says token is unauthorized, deleting token.");
-				eprintln!("ytmdctrl will need to reauthorize on next run");
-				let mut tkn_file = std::fs::File::create(get_token_store_path()).unwrap();
-				if let Some(mut store) = read_token_store() {
-					store.remove(command.get_server_addr());
-					tkn_file.write(&serde_json::to_vec(&store).unwrap()).unwrap();
-				}
-				return false;
-			} else {
-				eprintln!("-- Response Body --");
-				eprintln!("{}", serde_json::to_string_pretty(&parsed).unwrap())
-			}
-		} else {
-			eprintln!("-- Response Body (failed to parse json, unformatted) --");
-			eprintln!("{body}");
+	if !response.status().is_success() {
+		if response.status() == StatusCode::NOT_FOUND {
+			warn_on_api_mismatch(&client, &command).await;
 		}
-		std::process::exit(ERR_COMMAND_FAILED)
+		return Err(handle_failed_response(&command, response).await);
 	} else if command.is_get_request() {
+		check_is_ytmd(&command, &response)?;
 		let body = response.text().await.unwrap();
+		if let Command::State(BaseArgs { since: Some(ref last_id), .. }) = command {
+			let current_id = serde_json::from_str::<StateResponse>(&body).ok().and_then(|state| state.video.map(|v| v.id));
+			if current_id == Some(&**last_id) {
+				return Err(Stop::Exit(EXIT_UNCHANGED));
+			}
+		}
 		// attempt to parse the response as json so we can pretty print it
 		// if that fails, fallback to printing raw text
 		match command {
-			Command::State(_) => {
-				if let Ok(state) = serde_json::from_str::<StateResponse>(&*body) {
+			Command::State(BaseArgs { field: Some(ref path), .. }) => {
+				let Ok(parsed) = serde_json::from_str::<Value>(&body) else {
+					eprintln!("Unexpected response from YTMD -- unable to select `{path}`");
+					return Err(Stop::Exit(ERR_FIELD_MISSING));
+				};
+				match statejson::select_field(&parsed, path) {
+					Some(Value::String(s)) => println!("{s}"),
+					Some(value @ (Value::Array(_) | Value::Object(_))) => println!("{}", command.to_json(value)),
+					Some(value) => println!("{value}"),
+					None => {
+						eprintln!("No field `{path}` in the player state");
+						return Err(Stop::Exit(ERR_FIELD_MISSING));
+					},
+				}
+			},
+			Command::State(BaseArgs { template: Some(ref path), .. }) => {
+				let template = match std::fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|source| Template::parse(&source)) {
+					Ok(template) => template,
+					Err(e) => {
+						eprintln!("Unable to use template {}: {e}", path.display());
+						return Err(Stop::Exit(ERR_INVALID_ARGS));
+					},
+				};
+				let Ok(parsed) = serde_json::from_str::<Value>(&body) else {
+					eprintln!("Unexpected response from YTMD -- unable to render the template");
+					return Err(Stop::Exit(ERR_COMMAND_FAILED));
+				};
+				print!("{}", template.render(&parsed));
+			},
+			Command::State(BaseArgs { compact: true, no_unicode, .. }) => {
+				if let Ok(state) = serde_json::from_str::<StateResponse>(&body) {
+					let glyph = playback_glyph(&state.player.track_state, !no_unicode);
+					let volume = state.player.volume;
+					match &state.video {
+						Some(video) => println!(
+							"{glyph} {} - {}  {}/{}  vol {volume}%",
+							video.author,
+							video.title,
+							format_duration(state.player.video_progress),
+							usable_duration(video.duration_seconds).map_or_else(|| unknown_duration(video.is_live == Some(true)).to_string(), format_duration),
+						),
+						None => println!("{glyph} Nothing playing  vol {volume}%"),
+					}
+				} else {
+					eprintln!("Unexpected response from YTMD -- falling back to raw output");
+					println!("{body}");
+				}
+			},
+			Command::State(BaseArgs { automix_only: true, .. }) => {
+				if let Ok(state) = serde_json::from_str::<StateResponse>(&body) {
+					if let Some(queue) = &state.player.queue {
+						print_automix_queue(queue);
+					}
+				} else {
+					eprintln!("Unexpected response from YTMD -- falling back to raw output");
+					println!("{body}");
+				}
+			},
+			Command::State(BaseArgs { no_unicode, no_automix, art_width, .. }) => {
+				if let Ok(state) = serde_json::from_str::<StateResponse>(&body) {
+					#[cfg(feature = "image")]
+					if let (Some(cols), Some(video)) = (art_width, &state.video) {
+						print_art(&video.thumbnails, cols).await;
+					}
+					#[cfg(not(feature = "image"))]
+					let _ = art_width;
 					// if command.is_script_mode() {
-						println!("Status: {:?} {:?}", state.player.track_state, state.video.as_ref().map_or("", |v| v.title));
-						println!("Progress: {:?}s/{:?}s", state.player.video_progress, state.video.as_ref().map_or(0.0, |v| v.duration_seconds));
+						let title = state.video.as_ref().map_or("", |v| v.title);
+						if command.is_script_mode() {
+							println!("Status: {} {title:?}", state.player.track_state.to_script_str());
+						} else if no_unicode {
+							println!("Status: {:#} {title:?}", state.player.track_state);
+						} else {
+							println!("Status: {} {title:?}", state.player.track_state);
+						}
+						let duration = state.video.as_ref().and_then(|v| usable_duration(v.duration_seconds));
+						let live = state.video.as_ref().is_some_and(|v| v.is_live == Some(true));
+						if command.is_script_mode() {
+							let duration = duration.map_or_else(|| unknown_duration(live).to_string(), |d| format!("{d}s"));
+							println!("Progress: {}s/{duration}", state.player.video_progress);
+						} else {
+							let duration = duration.map_or_else(|| unknown_duration(live).to_string(), format_duration);
+							println!("Progress: {}/{duration}", format_duration(state.player.video_progress));
+						}
 						println!("Volume: {:?}%", state.player.volume);
 						if let Some(queue) = &state.player.queue {
-							let mut idx = 0;
-							println!("Queue:");
-							for video in queue.items.iter() {
-								print!("<{idx}> {}", video.title);
-								if video.selected {
-									println!(" <SELECTED>");
+							if command.is_script_mode() {
+								println!("Repeat: {}", queue.repeat_mode.to_script_str());
+							} else {
+								println!("Repeat: {:#}", queue.repeat_mode);
+							}
+							println!("Queue:{}", if queue.is_generating { " (still generating)" } else { "" });
+							// `selected_item_index` is authoritative; the items' own `selected` flags aren't always kept in sync
+							let selected = usize::try_from(queue.selected_item_index).ok();
+							if selected.is_none() {
+								println!("(no item selected)");
+							}
+							let arrow = if no_unicode { ">" } else { "▶" };
+							for (idx, video) in queue.items.iter().enumerate() {
+								if selected == Some(idx) {
+									println!("{arrow} <{idx}> {} <SELECTED>", video.title);
 								} else {
-									println!("");
+									println!("  <{idx}> {}", video.title);
 								}
-								idx += 1;
 							}
-							println!("Automix Queue:");
-							for video in &queue.automix_items {
-								println!("<{idx}> {}", video.title);
-								idx += 1;
+							if !no_automix {
+								print_automix_queue(queue);
 							}
 						}
 						
@@ -456,20 +1419,24 @@ async fn main_logic(command: Command, client: reqwest::Client, token: &str) -> b
 					// }
 				} else if let Ok(parsed) = serde_json::from_str::<Value>(&body) {
 					eprintln!("Unexpected response from YTMD -- falling back to unformatted output");
-					println!("{}", serde_json::to_string_pretty(&parsed).unwrap())
+					println!("{}", command.to_json(&parsed))
 				} else {
 					eprintln!("Unexpected response from YTMD -- falling back to raw output");
 					println!("{body}");
 				}
 			},
 			Command::Playlists(_) => {
-				if let Ok(playlists) = serde_json::from_str::<Vec<PlaylistEntry>>(&*body) {
+				if let Ok(playlists) = serde_json::from_str::<Vec<PlaylistEntry>>(&body) {
 					for pl in playlists {
-						println!("{} -> {}", pl.title, pl.id);
+						if command.is_script_mode() {
+							println!("{}\t{}", pl.id, escape_field(&pl.title));
+						} else {
+							println!("{} -> {}", pl.title, pl.id);
+						}
 					}
 				} else if let Ok(parsed) = serde_json::from_str::<Value>(&body) {
 					eprintln!("Unexpected response from YTMD -- falling back to unformatted output");
-					println!("{}", serde_json::to_string_pretty(&parsed).unwrap())
+					println!("{}", command.to_json(&parsed))
 				} else {
 					eprintln!("Unexpected response from YTMD -- falling back to raw output");
 					println!("{body}");
@@ -477,19 +1444,1028 @@ async fn main_logic(command: Command, client: reqwest::Client, token: &str) -> b
 			},
 			_ => {
 				if let Ok(parsed) = serde_json::from_str::<Value>(&body) {
-				println!("{}", serde_json::to_string_pretty(&parsed).unwrap())
+					println!("{}", command.to_json(&parsed))
 				} else {
 					println!("{body}");
 				}
 			}
 		}
+	} else if let Command::Open(VideoChangeRequestArgs { index, play, .. }) = command {
+		if index.is_some() || play {
+			// the new queue takes a moment to load, and jumping or playing before it has does nothing
+			let needed = index.map_or(1, |index| index as usize + 1);
+			let loaded = wait_for_state(&client, &command, token, std::time::Duration::from_secs(2), QUEUE_LOAD_TIMEOUT, |state| {
+				state.player.queue.as_ref().is_some_and(|queue| queue.items.len() >= needed)
+			}).await;
+			match (loaded, index) {
+				(Ok(true), _) => (),
+				(Ok(false), Some(index)) => {
+					eprintln!("The queue didn't load far enough to start at index {index}");
+					return Err(Stop::Exit(ERR_COMMAND_FAILED));
+				},
+				(Ok(false), None) => eprintln!("The queue didn't load in time; starting playback anyway"),
+				(Err(stop), _) => return Err(stop),
+			}
+			if let Some(index) = index {
+				send_command(&client, &command, token, format!(r#"{{"command":"playQueueIndex", "data": {}}}"#, index)).await?;
+			}
+			if play {
+				tokio::time::sleep(COMMAND_INTERVAL).await;
+				send_command(&client, &command, token, String::from(r#"{"command":"play"}"#)).await?;
+			}
+		}
+	} else if let Command::Play(BaseArgs { wait_until_playing: true, .. }) = command {
+		let playing = wait_for_state(&client, &command, token, std::time::Duration::from_secs(1), PLAYBACK_START_TIMEOUT, |state| {
+			matches!(state.player.track_state, PlaybackState::Playing)
+		}).await;
+		match playing {
+			Ok(true) => (),
+			Ok(false) => {
+				eprintln!("Playback didn't start");
+				return Err(Stop::Exit(ERR_NOT_CONFIRMED));
+			},
+			Err(stop) => return Err(stop),
+		}
+	} else if let Some(expected) = command.confirm_muted() {
+		// give the player a moment to apply the command before checking on it
+		tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+		let body = get_state(&client, &command, token).await?;
+		let Ok(state) = serde_json::from_str::<StateResponse>(&body) else {
+			eprintln!("Unable to confirm command: unexpected state response from YTMD");
+			return Err(Stop::Exit(ERR_NOT_CONFIRMED));
+		};
+		match state.player.muted {
+			Some(muted) if muted == expected => (),
+			Some(_) => {
+				if state.player.ad_playing {
+					eprintln!("Command did not take effect (an ad is currently playing)");
+				} else {
+					eprintln!("Command did not take effect");
+				}
+				return Err(Stop::Exit(ERR_NOT_CONFIRMED));
+			},
+			None => {
+				eprintln!("Unable to confirm command: YTMD did not report a mute status");
+				return Err(Stop::Exit(ERR_NOT_CONFIRMED));
+			},
+		}
+	}
+	Ok(())
+}
+
+// Automix items aren't part of the queue until they're played, so they're numbered apart from it.
+fn print_automix_queue(queue: &QueueState) {
+	println!("Automix Queue:");
+	for (idx, video) in queue.automix_items.iter().enumerate() {
+		println!("  <A{idx}> {}", video.title);
+	}
+}
+
+// `state --art-width`: draws the song's thumbnail, or says why it couldn't and carries on with the rest of the state.
+#[cfg(feature = "image")]
+async fn print_art(thumbnails: &[statejson::ThumbnailState<'_>], cols: u32) {
+	let Some(thumbnail) = art::pick_thumbnail(thumbnails, cols) else {
+		eprintln!("The song has no album art");
+		return;
+	};
+	// the art is on YouTube's servers, so none of what the YTMD client is set up with (headers, address family, redirects) applies
+	let client = reqwest::Client::builder()
+		.user_agent(USER_AGENT)
+		.connect_timeout(CONNECT_TIMEOUT)
+		.build()
+		.unwrap();
+	let bytes = match client.get(thumbnail.url).send().await.and_then(reqwest::Response::error_for_status) {
+		Ok(response) => response.bytes().await,
+		Err(e) => Err(e),
+	};
+	match bytes.map_err(|e| e.to_string()).and_then(|bytes| image::load_from_memory(&bytes).map_err(|e| e.to_string())) {
+		Ok(image) => print!("{}", art::render(&image, cols)),
+		Err(e) => eprintln!("Unable to load the album art from {}: {e}", thumbnail.url),
+	}
+}
+
+// A symbol for the playback status in one-line output, or an ASCII stand-in for terminals without the symbols.
+fn playback_glyph(track_state: &PlaybackState, unicode: bool) -> &'static str {
+	match (track_state, unicode) {
+		(PlaybackState::Playing, true) => "▶",
+		(PlaybackState::Playing, false) => ">",
+		(PlaybackState::Paused, true) => "⏸",
+		(PlaybackState::Paused, false) => "||",
+		(PlaybackState::Buffering, true) => "…",
+		(PlaybackState::Buffering, false) => "...",
+		(PlaybackState::Unknown, _) => "?",
+	}
+}
+
+// Makes text safe to put in a tab-separated line: backslashes, tabs and line breaks become `\\`, `\t`, `\n` and `\r`.
+fn escape_field(text: &str) -> String {
+	let mut escaped = String::with_capacity(text.len());
+	for c in text.chars() {
+		match c {
+			'\\' => escaped.push_str("\\\\"),
+			'\t' => escaped.push_str("\\t"),
+			'\n' => escaped.push_str("\\n"),
+			'\r' => escaped.push_str("\\r"),
+			c => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+// Explains a request that never got a response, rather than panicking over it.
+fn report_send_error(command: &Command, e: reqwest::Error) -> Stop {
+	let base_url = command.get_base_url();
+	if e.is_connect() {
+		eprintln!("Unable to connect to YTMD at {base_url}");
+		eprintln!("Make sure YTMD is running with its companion server enabled (Settings > Integrations > Companion server)");
+	} else if e.is_timeout() {
+		eprintln!("YTMD at {base_url} didn't respond in time");
+	} else {
+		eprintln!("Request to YTMD at {base_url} failed: {e}");
+	}
+	Stop::Exit(ERR_COMMAND_FAILED)
+}
+
+// Stops with a short explanation if the response clearly didn't come from YTMD,
+// e.g. `--server` points at a router's admin page, rather than dumping whatever it sent back.
+fn check_is_ytmd(command: &Command, response: &reqwest::Response) -> Outcome {
+	let reason = if response.status().is_redirection() {
+		let location = response.headers().get(reqwest::header::LOCATION).and_then(|v| v.to_str().ok()).unwrap_or("elsewhere");
+		format!("it redirected to {location}")
+	} else {
+		match response.headers().get(reqwest::header::CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+			Some(content_type) if !content_type.contains("json") => format!("it responded with {content_type}"),
+			_ => return Ok(()),
+		}
+	};
+	eprintln!("The server at {}:{} doesn't look like YTMD ({reason})", command.common_args().url_host(), command.common_args().port());
+	eprintln!("Check the --server and --port options");
+	Err(Stop::Exit(ERR_COMMAND_FAILED))
+}
+
+// Reports a non-success response from YTMD, and how the command it was for should stop:
+// with `RateLimited` or `TokenRejected` if that's why it failed, or else with an error code.
+async fn handle_failed_response(command: &Command, response: reqwest::Response) -> Stop {
+	if response.status() == StatusCode::TOO_MANY_REQUESTS {
+		eprintln!("Rate limit exceeded");
+		eprintln!("Wait {} seconds before submitting another request", 
+			response.headers().get("x-ratelimit-reset").and_then(|v| v.to_str().ok()).unwrap_or("5")
+		);
+		return Stop::RateLimited;
+	}
+	if let Err(stop) = check_is_ytmd(command, &response) {
+		return stop;
+	}
+	eprintln!("Command sent to YTMD Failed: {response:#?}");
+	let body = response.text().await.unwrap();
+	if let Ok(parsed) = serde_json::from_str::<Value>(&body) {
+		if parsed.get("error").is_some_and(|e| e.as_str() == Some("UNAUTHORIZED")) {
+			// UNAUTHORIZED means our current token is invalid
+			eprintln!("Server says token is unauthorized, deleting token.");
+			eprintln!("ytmdctrl will need to reauthorize on next run");
+			return Stop::TokenRejected;
+		} else {
+			eprintln!("-- Response Body --");
+			eprintln!("{}", command.to_json(&parsed))
+		}
+	} else {
+		eprintln!("-- Response Body (failed to parse json, unformatted) --");
+		eprintln!("{body}");
+	}
+	Stop::Exit(ERR_COMMAND_FAILED)
+}
+
+// Polls the state until `ready` holds for it, checking first after `first_check` and giving up after `timeout`.
+// `Ok` says whether `ready` was met.
+async fn wait_for_state(
+	client: &reqwest::Client,
+	command: &Command,
+	token: &str,
+	first_check: std::time::Duration,
+	timeout: std::time::Duration,
+	ready: impl Fn(&StateResponse) -> bool,
+) -> Outcome<bool> {
+	let deadline = tokio::time::Instant::now() + timeout;
+	tokio::time::sleep(first_check).await;
+	loop {
+		let body = get_state(client, command, token).await?;
+		if serde_json::from_str::<StateResponse>(&body).is_ok_and(|state| ready(&state)) {
+			return Ok(true);
+		}
+		if tokio::time::Instant::now() + STATE_POLL_INTERVAL > deadline {
+			return Ok(false);
+		}
+		tokio::time::sleep(STATE_POLL_INTERVAL).await;
+	}
+}
+
+// YTMD misbehaves when told to play a song on a playlist it isn't on. There's no asking which songs a playlist has,
+// but when it's the one already loaded the queue says, so a song clearly not on it can be refused before anything changes.
+async fn check_on_playlist(client: &reqwest::Client, command: &Command, token: &str, video: &str, playlist: &str) -> Outcome {
+	let Some(body) = peek_state(client, command, token).await else {
+		return Ok(());
+	};
+	let Ok(state) = serde_json::from_str::<StateResponse>(&body) else {
+		return Ok(());
+	};
+	let Some(queue) = state.player.queue.filter(|queue| state.playlist_id == playlist && !queue.is_generating) else {
+		return Ok(());
+	};
+	if !queue.items.is_empty() && !queue.items.iter().any(|item| item.video_id == video) {
+		eprintln!("`{video}` isn't on playlist `{playlist}`; leave out --video to play the playlist from its start");
+		return Err(Stop::Exit(ERR_INVALID_ARGS));
+	}
+	Ok(())
+}
+
+// Posts a command body on behalf of commands that make more than one request.
+async fn send_command(client: &reqwest::Client, command: &Command, token: &str, body: String) -> Outcome {
+	let response = post_command(client, command, token, body).await?;
+	if response.status().is_success() {
+		Ok(())
+	} else {
+		Err(handle_failed_response(command, response).await)
+	}
+}
+
+// `--dump-headers`: shows a response's status and headers before anything is made of it.
+fn dump_headers(command: &Command, response: &reqwest::Response) {
+	if !command.common_args().dump_headers() {
+		return;
+	}
+	eprintln!("{} {}", response.status(), response.url());
+	for (name, value) in response.headers() {
+		eprintln!("{name}: {}", String::from_utf8_lossy(value.as_bytes()));
+	}
+}
+
+async fn post_command(client: &reqwest::Client, command: &Command, token: &str, body: String) -> Outcome<reqwest::Response> {
+	let response = client.post(command.get_api_url("command"))
+		.header("content-type", "application/json")
+		.header("Authorization", token)
+		.body(body)
+		.send().await.map_err(|e| report_send_error(command, e))?;
+	dump_headers(command, &response);
+	Ok(response)
+}
+
+// Points out when a 404 is down to the server having moved on from the API version ytmdctrl speaks.
+// Servers that don't say which versions they support are given the benefit of the doubt.
+async fn warn_on_api_mismatch(client: &reqwest::Client, command: &Command) {
+	let Ok(response) = client.get(format!("{}/metadata", command.get_base_url())).send().await else {
+		return;
+	};
+	let Some(metadata) = response.text().await.ok().and_then(|body| serde_json::from_str::<Value>(&body).ok()) else {
+		return;
+	};
+	let Some(versions) = metadata["apiVersions"].as_array() else {
+		return;
+	};
+	if !versions.iter().any(|v| v.as_str() == Some(API_VERSION)) {
+		let versions: Vec<&str> = versions.iter().filter_map(Value::as_str).collect();
+		eprintln!("ytmdctrl speaks version {API_VERSION} of the companion server API, but this YTMD only supports {}", versions.join(", "));
+		eprintln!("An update to ytmdctrl is likely needed");
+	}
+}
+
+// Posts each of `bodies` in turn, stopping at the first that fails.
+async fn send_in_sequence(client: &reqwest::Client, command: &Command, token: &str, bodies: &[&str]) -> Outcome {
+	for body in bodies {
+		send_command(client, command, token, body.to_string()).await?;
+	}
+	Ok(())
+}
+
+// Sends `command`'s body `count` times, for `next`/`previous` with a count.
+async fn skip(client: &reqwest::Client, command: &Command, token: &str, count: u32) -> Outcome {
+	if count > MAX_SKIPS {
+		eprintln!("Skipping {MAX_SKIPS} songs rather than {count}");
+	}
+	let count = count.min(MAX_SKIPS);
+	for done in 0..count {
+		if done > 0 {
+			tokio::time::sleep(COMMAND_INTERVAL).await;
+		}
+		let response = post_command(client, command, token, command.get_body()).await?;
+		if !response.status().is_success() {
+			eprintln!("Skipped {done} of {count} songs");
+			return Err(handle_failed_response(command, response).await);
+		}
+	}
+	Ok(())
+}
+
+// Fetches the raw player state, for commands that need to check on the player around their own request.
+async fn get_state(client: &reqwest::Client, command: &Command, token: &str) -> Outcome<String> {
+	let response = client.get(command.get_api_url("state"))
+		.header("Authorization", token)
+		.send().await.map_err(|e| report_send_error(command, e))?;
+	dump_headers(command, &response);
+	if response.status().is_success() {
+		check_is_ytmd(command, &response)?;
+		Ok(response.text().await.unwrap())
+	} else {
+		Err(handle_failed_response(command, response).await)
+	}
+}
+
+// Fetches the player state for a check that's only a precaution, so that the command goes ahead when it can't be read,
+// e.g. because of YTMD's rate limit on it. `None` means it couldn't, which is left for the command itself to report.
+async fn peek_state(client: &reqwest::Client, command: &Command, token: &str) -> Option<String> {
+	let response = client.get(command.get_api_url("state"))
+		.header("Authorization", token)
+		.send().await.ok()?;
+	dump_headers(command, &response);
+	if !response.status().is_success() {
+		return None;
+	}
+	response.text().await.ok()
+}
+
+// Prints the time left in the queue: the rest of the current song plus every song queued after it.
+async fn print_remaining(client: &reqwest::Client, command: &Command, token: &str) -> Outcome {
+	let body = get_state(client, command, token).await?;
+	let Ok(state) = serde_json::from_str::<StateResponse>(&body) else {
+		eprintln!("Unexpected state response from YTMD");
+		return Err(Stop::Exit(ERR_COMMAND_FAILED));
+	};
+	let mut remaining = 0.0;
+	let mut growing = false;
+	if let Some(queue) = &state.player.queue {
+		let selected = usize::try_from(queue.selected_item_index).ok();
+		// with nothing selected, the whole queue is still to come
+		let upcoming = selected.map_or(0, |idx| idx + 1);
+		if selected.is_some() {
+			if let Some(duration) = state.video.as_ref().and_then(|v| usable_duration(v.duration_seconds)) {
+				remaining += (duration - state.player.video_progress).max(0.0);
+			}
+		}
+		remaining += queue.items.iter()
+			.skip(upcoming)
+			.filter_map(|item| timefmt::parse_duration_hms(item.duration))
+			.sum::<f32>();
+		growing = queue.is_infinite || queue.is_generating;
+	}
+	let suffix = if growing { "+" } else { "" };
+	if command.is_script_mode() {
+		println!("{remaining}{suffix}");
+	} else {
+		println!("{}{suffix}", format_duration(remaining));
+	}
+	Ok(())
+}
+
+// Reports the like status through the exit code so scripts can branch on it directly.
+async fn print_like_status(client: &reqwest::Client, command: &Command, token: &str) -> Outcome {
+	let body = get_state(client, command, token).await?;
+	let like_status = serde_json::from_str::<StateResponse>(&body).ok()
+		.and_then(|state| state.video)
+		.and_then(|video| video.like_status)
+		.unwrap_or(LikeState::Unknown);
+	if !command.is_quiet() {
+		if command.is_script_mode() {
+			println!("{}", like_status.to_script_str());
+		} else {
+			println!("{like_status}");
+		}
+	}
+	Err(Stop::Exit(match like_status {
+		LikeState::Like => EXIT_LIKED,
+		LikeState::Indifferent => EXIT_INDIFFERENT,
+		LikeState::Dislike => EXIT_DISLIKED,
+		LikeState::Unknown => EXIT_LIKE_UNKNOWN,
+	}))
+}
+
+// `volume-up`/`volume-down` with a limit: steps as usual unless that would cross the limit, in which case the volume is set to it.
+async fn step_volume_within(client: &reqwest::Client, command: &Command, token: &str, min: u8, max: u8) -> Outcome {
+	let body = get_state(client, command, token).await?;
+	let Ok(state) = serde_json::from_str::<StateResponse>(&body) else {
+		eprintln!("Unexpected state response from YTMD");
+		return Err(Stop::Exit(ERR_COMMAND_FAILED));
+	};
+	let volume = state.player.volume;
+	let stepped = if let Command::VolumeUp(_) = command {
+		volume.saturating_add(VOLUME_STEP)
+	} else {
+		volume.saturating_sub(VOLUME_STEP)
+	};
+	let body = if stepped > max || stepped < min {
+		format!(r#"{{"command":"setVolume", "data": {}}}"#, stepped.clamp(min, max))
+	} else {
+		command.get_body()
+	};
+	send_command(client, command, token, body).await?;
+	Ok(())
+}
+
+// `ensure-playing`/`ensure-paused`: sends `play` or `pause` only if the player isn't in that state already,
+// so running either twice is the same as running it once. Buffering counts as playing.
+async fn ensure_playback(client: &reqwest::Client, command: &Command, token: &str) -> Outcome {
+	let body = get_state(client, command, token).await?;
+	let Ok(state) = serde_json::from_str::<StateResponse>(&body) else {
+		eprintln!("Unexpected state response from YTMD");
+		return Err(Stop::Exit(ERR_COMMAND_FAILED));
+	};
+	let playing = matches!(state.player.track_state, PlaybackState::Playing | PlaybackState::Buffering);
+	let paused = matches!(state.player.track_state, PlaybackState::Paused);
+	let already = if let Command::EnsurePlaying(_) = command { playing } else { paused };
+	if already {
+		return Ok(());
 	}
-	return true;
+	send_command(client, command, token, command.get_body()).await?;
+	Ok(())
 }
 
-//TODO: Support non-unix operating systems
-#[cfg(target_family="unix")]
-fn owner_only() -> Permissions {
-    use std::os::unix::fs::PermissionsExt as _;
-    Permissions::from_mode(0o600)
-}
\ No newline at end of file
+// YTMD doesn't tell whether it's muted, so `mute-toggle` mutes by setting the volume to 0 and keeps the volume
+// from before in a file of its own. A saved volume means the next toggle restores it; otherwise it mutes,
+// unless the player is at 0 already, when it's turned up to `UNMUTE_VOLUME`.
+async fn mute_toggle(client: &reqwest::Client, command: &Command, token: &str) -> Outcome {
+	let path = get_muted_volumes_path();
+	let mut saved: HashMap<String, u8> = std::fs::read_to_string(path).ok()
+		.and_then(|contents| serde_json::from_str(&contents).ok())
+		.unwrap_or_default();
+	// kept apart per server the same way tokens are
+	let server = command.get_token_key();
+	let volume = match saved.remove(&server) {
+		Some(volume) => volume,
+		None => {
+			let body = get_state(client, command, token).await?;
+			let Ok(state) = serde_json::from_str::<StateResponse>(&body) else {
+				eprintln!("Unexpected state response from YTMD");
+				return Err(Stop::Exit(ERR_COMMAND_FAILED));
+			};
+			if state.player.volume == 0 {
+				UNMUTE_VOLUME
+			} else {
+				saved.insert(server, state.player.volume);
+				0
+			}
+		},
+	};
+	send_command(client, command, token, format!(r#"{{"command":"setVolume", "data": {volume}}}"#)).await?;
+	let written = std::fs::create_dir_all(path.parent().unwrap())
+		.and_then(|()| std::fs::write(path, serde_json::to_vec(&saved).unwrap()));
+	if let Err(e) = written {
+		eprintln!("Unable to save the volume to {}: {e}", path.display());
+	}
+	Ok(())
+}
+
+// There's no way back to the old order after a shuffle, so make sure it was meant.
+// Only asks when someone is there to answer; in script mode `--yes` has to be given instead.
+fn confirm_shuffle(command: &Command) -> Outcome {
+	use std::io::IsTerminal as _;
+	if command.is_script_mode() {
+		eprintln!("`shuffle` can't be undone, so it requires --yes in script mode");
+		return Err(Stop::Exit(ERR_NOT_CONFIRMED));
+	}
+	if !std::io::stdout().is_terminal() {
+		return Ok(());
+	}
+	eprint!("Shuffle the queue? Its current order will be lost. [y/N] ");
+	let mut answer = String::new();
+	let _ = std::io::stdin().read_line(&mut answer);
+	if !matches!(&*answer.trim().to_lowercase(), "y" | "yes") {
+		eprintln!("Not shuffling");
+		return Err(Stop::Exit(ERR_NOT_CONFIRMED));
+	}
+	Ok(())
+}
+
+// Prints what's needed to identify the current song elsewhere, ids included.
+async fn print_track_info(client: &reqwest::Client, command: &Command, token: &str, json: bool) -> Outcome {
+	let body = get_state(client, command, token).await?;
+	let Ok(state) = serde_json::from_str::<StateResponse>(&body) else {
+		eprintln!("Unexpected state response from YTMD");
+		return Err(Stop::Exit(ERR_COMMAND_FAILED));
+	};
+	let Some(video) = state.video else {
+		eprintln!("Nothing is playing");
+		return Err(Stop::Exit(ERR_FIELD_MISSING));
+	};
+	let fields = [
+		("author", Some(video.author)),
+		("title", Some(video.title)),
+		("album", video.album),
+		("album_id", video.album_id),
+		("channel_id", Some(video.channel_id)),
+		("id", Some(video.id)),
+		("video_type", video.video_type.as_ref().map(VideoType::to_script_str)),
+	];
+	if json {
+		let object: serde_json::Map<String, Value> = fields.into_iter()
+			.map(|(key, value)| (key.to_string(), value.map_or(Value::Null, Value::from)))
+			.collect();
+		println!("{}", command.to_json(&object));
+	} else {
+		for (key, value) in fields {
+			println!("{key}={}", value.unwrap_or(""));
+		}
+	}
+	Ok(())
+}
+
+// Seeking in a live stream desyncs playback since there's no meaningful position to seek to, so `seek` refuses to.
+async fn check_not_live(client: &reqwest::Client, command: &Command, token: &str) -> Outcome {
+	// not being able to tell isn't a reason to refuse
+	let Some(body) = peek_state(client, command, token).await else {
+		return Ok(());
+	};
+	let Ok(state) = serde_json::from_str::<StateResponse>(&body) else {
+		return Ok(());
+	};
+	if state.video.and_then(|v| v.is_live) == Some(true) {
+		eprintln!("Refusing to seek in a live stream; use --force to seek anyway");
+		return Err(Stop::Exit(ERR_COMMAND_FAILED));
+	}
+	Ok(())
+}
+
+// Swaps the selected queue item for its audio/video counterpart, then seeks back to where it was.
+async fn switch_version(client: &reqwest::Client, command: &Command, token: &str) -> Outcome {
+	let body = get_state(client, command, token).await?;
+	let Ok(state) = serde_json::from_str::<StateResponse>(&body) else {
+		eprintln!("Unexpected state response from YTMD");
+		return Err(Stop::Exit(ERR_COMMAND_FAILED));
+	};
+	let selected = state.player.queue.as_ref().and_then(|queue| {
+		usize::try_from(queue.selected_item_index).ok().and_then(|idx| queue.items.get(idx))
+	});
+	let Some(selected) = selected else {
+		eprintln!("Nothing in the queue is selected");
+		return Ok(());
+	};
+	let Some(counterpart) = selected.counterparts.as_ref().and_then(|c| c.first()) else {
+		eprintln!("`{}` has no alternate audio/video version", selected.title);
+		return Ok(());
+	};
+	// the counterpart usually isn't part of the current playlist, so it's opened on its own
+	send_command(client, command, token, change_video_body(Some(counterpart.video_id), None)).await?;
+	// give the player a moment to load the new video before seeking in it
+	tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+	let progress = state.player.video_progress;
+	send_command(client, command, token, format!(r#"{{"command":"seekTo", "data": {}}}"#, progress)).await?;
+	Ok(())
+}
+
+// Longest `--wait-connection` waits between attempts; it starts out trying again much sooner.
+const CONNECTION_RETRY_MAX: std::time::Duration = std::time::Duration::from_secs(5);
+
+// `--wait-connection`: tries reaching the companion server until it answers at all, backing off between attempts.
+// Any response will do, since this is only about the server being up; exits if it isn't by `timeout`.
+async fn wait_for_connection(client: &reqwest::Client, command: &Command, timeout: std::time::Duration) {
+	let deadline = tokio::time::Instant::now() + timeout;
+	let mut backoff = std::time::Duration::from_millis(250);
+	loop {
+		let attempt = client.get(format!("{}/metadata", command.get_base_url())).send();
+		match tokio::time::timeout_at(deadline, attempt).await {
+			Ok(Ok(_)) => return,
+			Ok(Err(e)) if !e.is_connect() && !e.is_timeout() => exit_process(report_send_error(command, e).code()),
+			Ok(Err(_)) => (),
+			Err(_) => break,
+		}
+		if tokio::time::Instant::now() + backoff > deadline {
+			break;
+		}
+		tokio::time::sleep(backoff).await;
+		backoff = (backoff * 2).min(CONNECTION_RETRY_MAX);
+	}
+	eprintln!("Timed out after {} waiting for YTMD at {} to be reachable", format_duration(timeout.as_secs_f32()), command.get_base_url());
+	eprintln!("Make sure YTMD is running with its companion server enabled (Settings > Integrations > Companion server)");
+	exit_process(ERR_COMMAND_FAILED);
+}
+
+// How often the player state is polled, matching YTMD's rate limit for it.
+const STATE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+// Roughly how long a seek takes to land, so `loop` seeks a little early instead of overshooting.
+const SEEK_LATENCY: f32 = 0.25;
+// How far YTMD moves the volume for `volumeUp`/`volumeDown`, in percent.
+const VOLUME_STEP: u8 = 10;
+// What `mute-toggle` unmutes to when it finds the player at 0 with no volume of its own to go back to.
+const UNMUTE_VOLUME: u8 = 50;
+
+// Returns once the song that's playing now is over: another one started, it started over (repeating it),
+// or playback stopped. Gives up with `EXIT_UNCHANGED` after `timeout`.
+async fn wait_track_end(
+	client: &reqwest::Client,
+	command: &Command,
+	token: &str,
+	poll_interval: std::time::Duration,
+	timeout: Option<std::time::Duration>,
+) -> Outcome {
+	let mut deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+	let mut current: Option<(String, f32)> = None;
+	loop {
+		let polled_at = tokio::time::Instant::now();
+		let body = get_state(client, command, token).await?;
+		let Ok(state) = serde_json::from_str::<StateResponse>(&body) else {
+			eprintln!("Unexpected state response from YTMD");
+			return Err(Stop::Exit(ERR_COMMAND_FAILED));
+		};
+		let (PlaybackState::Playing | PlaybackState::Buffering, Some(video)) = (&state.player.track_state, &state.video) else {
+			return Ok(());
+		};
+		let progress = state.player.video_progress;
+		match &mut current {
+			Some((id, last_progress)) => {
+				if id != video.id || progress < *last_progress {
+					return Ok(());
+				}
+				*last_progress = progress;
+			},
+			None => {
+				current = Some((video.id.to_string(), progress));
+				// without a duration to go by (a live stream, say) there's no telling when it ends, so it's waited on until it does
+				if let Some(duration) = usable_duration(video.duration_seconds) {
+					let remaining = (duration - progress).max(0.0);
+					deadline.get_or_insert(polled_at + std::time::Duration::from_secs_f32(remaining) + std::time::Duration::from_secs(60));
+				}
+			},
+		}
+		if deadline.is_some_and(|deadline| polled_at + poll_interval > deadline) {
+			eprintln!("`{}` is still playing", video.title);
+			return Err(Stop::Exit(EXIT_UNCHANGED));
+		}
+		tokio::time::sleep_until(polled_at + poll_interval).await;
+	}
+}
+
+// Client-side A-B repeat. The state can only be read every few seconds, so rather than
+// waiting to see playback pass `end` this predicts when it will and schedules the seek for then.
+async fn ab_loop(client: &reqwest::Client, command: &Command, token: &str, start: f32, end: f32) -> Outcome {
+	let seek_to_start = format!(r#"{{"command":"seekTo", "data": {}}}"#, start);
+	let mut looped_video: Option<String> = None;
+	loop {
+		let polled_at = tokio::time::Instant::now();
+		let body = get_state(client, command, token).await?;
+		let Ok(state) = serde_json::from_str::<StateResponse>(&body) else {
+			eprintln!("Unexpected state response from YTMD");
+			return Err(Stop::Exit(ERR_COMMAND_FAILED));
+		};
+		let video_id = state.video.as_ref().map(|v| v.id);
+		match (&looped_video, video_id) {
+			(Some(looped), Some(id)) if looped != id => {
+				eprintln!("The song changed, stopping the loop");
+				return Ok(());
+			},
+			(None, Some(id)) => looped_video = Some(id.to_string()),
+			_ => (),
+		}
+		let progress = state.player.video_progress;
+		if matches!(state.player.track_state, PlaybackState::Playing) && !state.player.ad_playing {
+			if progress < start || progress >= end - SEEK_LATENCY {
+				send_command(client, command, token, seek_to_start.clone()).await?;
+			} else {
+				let until_end = end - SEEK_LATENCY - progress;
+				if until_end < STATE_POLL_INTERVAL.as_secs_f32() {
+					tokio::time::sleep(std::time::Duration::from_secs_f32(until_end)).await;
+					send_command(client, command, token, seek_to_start.clone()).await?;
+				}
+			}
+		}
+		tokio::time::sleep_until(polled_at + STATE_POLL_INTERVAL).await;
+	}
+}
+
+// Parses one line of a `run` script, or one sent to the daemon, into the command it stands for, aimed at `server`.
+fn parse_script_line(line: &str, server: &str) -> Result<Command, String> {
+	let mut args: Vec<String> = line.split_whitespace().map(String::from).collect();
+	// in the same order as for the command line, so that a line runs just as it would there
+	split_long_options(&mut args);
+	join_delay_until(&mut args);
+	apply_command_defaults(&mut args);
+	args.extend([String::from("--server"), server.to_string()]);
+	let command = match Command::from_args(args.iter().map(|s| &**s)) {
+		Ok(command) => command,
+		Err(ParseKind::Sub(_, ParseError::InvalidFlagValue(name, value))) => return Err(format!("Invalid value `{value}` for `{name}`")),
+		Err(_) => return Err(format!("Invalid command `{line}`")),
+	};
+	if !command.runs_in_process() {
+		return Err(format!("`{}` can't be run from a script or the daemon", line.split_whitespace().next().unwrap_or(line)));
+	}
+	check_args(&command)?;
+	Ok(command)
+}
+
+// Runs each line of a script through `main_logic`, sharing this command's client and token.
+async fn run_script(client: &reqwest::Client, command: &Command, token: &str, script: &Path, continue_on_error: bool) -> Outcome {
+	let contents = match std::fs::read_to_string(script) {
+		Ok(contents) => contents,
+		Err(e) => {
+			eprintln!("Unable to read {}: {e}", script.display());
+			return Err(Stop::Exit(ERR_INVALID_ARGS));
+		},
+	};
+	let server = format!("{}:{}", command.common_args().url_host(), command.common_args().port());
+	// parse everything first, so a typo near the end doesn't leave the script half done
+	let mut lines = Vec::new();
+	for (idx, line) in contents.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		match parse_script_line(line, &server) {
+			Ok(line_command) => lines.push((idx + 1, line_command)),
+			Err(e) => {
+				eprintln!("{}:{}: {e}", script.display(), idx + 1);
+				return Err(Stop::Exit(ERR_INVALID_ARGS));
+			},
+		}
+	}
+	let mut failed = false;
+	for (idx, (line_no, line_command)) in lines.into_iter().enumerate() {
+		// the lines are sent back to back, so they're paced like any other run of commands
+		if idx > 0 {
+			tokio::time::sleep(COMMAND_INTERVAL).await;
+		}
+		let code = match run_in_process(line_command, client, token).await {
+			Ok(()) => continue,
+			// the token was rejected, so no later line would get through either
+			Err(Stop::TokenRejected) => return Err(Stop::TokenRejected),
+			// the line didn't happen, which the lines after it may well rely on
+			Err(Stop::RateLimited) => ERR_COMMAND_FAILED,
+			Err(Stop::Exit(code)) => code,
+		};
+		if code != 0 {
+			eprintln!("{}:{line_no}: failed with exit code {code}", script.display());
+			if !continue_on_error {
+				return Err(Stop::Exit(code));
+			}
+			failed = true;
+		}
+	}
+	if failed {
+		return Err(Stop::Exit(ERR_COMMAND_FAILED));
+	}
+	Ok(())
+}
+
+// Runs one of the commands `run` or `daemon` was given through `main_logic`, sharing their client and token.
+async fn run_in_process(command: Command, client: &reqwest::Client, token: &str) -> Outcome {
+	// boxed, since this is called from within `main_logic`
+	Box::pin(main_logic(command, client.clone(), token)).await
+}
+
+// What the daemon answers a client naming the server its commands are for.
+const DAEMON_SAME_SERVER: &str = "ok";
+const DAEMON_OTHER_SERVER: &str = "other-server";
+
+// Hands the command line to a running daemon for `server` and waits for its exit code.
+// `None` means there's no such daemon to hand it to, so the command should run directly.
+// `delay` is waited out here rather than in the daemon, so that interrupting it cancels the command.
+async fn send_to_daemon(args: &[String], server: &str, delay: Option<std::time::Duration>) -> Option<i32> {
+	use std::io::BufRead as _;
+	let mut args: Vec<&str> = args.iter().map(|a| &**a).filter(|a| *a != "--via-daemon").collect();
+	// the daemon splits lines on whitespace; an `until` delay is the one argument it knows to put back together
+	if args.iter().any(|a| a.contains(char::is_whitespace) && !a.starts_with("until ")) {
+		return None;
+	}
+	let mut stream = std::os::unix::net::UnixStream::connect(get_daemon_socket_path()).ok()?;
+	let mut reader = std::io::BufReader::new(stream.try_clone().ok()?);
+	writeln!(stream, "{server}").ok()?;
+	let mut reply = String::new();
+	reader.read_line(&mut reply).ok()?;
+	if reply.trim() != DAEMON_SAME_SERVER {
+		return None;
+	}
+	if let Some(delay) = delay {
+		wait_out_delay(delay).await;
+	}
+	// last one wins, so this also stands in for a delay the daemon would take from `config.toml`
+	args.extend(["--delay", "0"]);
+	// from here on the delay is over and the daemon may have run the command, so it mustn't be run again
+	reply.clear();
+	let replied = writeln!(stream, "{}", args.join(" ")).and_then(|()| reader.read_line(&mut reply));
+	Some(replied.ok().and_then(|_| reply.trim().parse().ok()).unwrap_or_else(|| {
+		eprintln!("The daemon stopped without reporting back");
+		ERR_COMMAND_FAILED
+	}))
+}
+
+// Listens on the daemon socket and runs each line received as a command, replying with its exit code.
+// Only returns once a command finds the token rejected, with `Stop::TokenRejected`.
+async fn run_daemon(client: &reqwest::Client, command: &Command, token: &str) -> Outcome {
+	let path = get_daemon_socket_path();
+	if std::os::unix::net::UnixStream::connect(path).is_ok() {
+		eprintln!("A daemon is already listening on {}", path.display());
+		return Err(Stop::Exit(ERR_COMMAND_FAILED));
+	}
+	// nothing is listening, so whatever is there was left behind by a daemon that didn't get to clean up
+	let _ = std::fs::remove_file(path);
+	let listener = match tokio::net::UnixListener::bind(path) {
+		Ok(listener) => listener,
+		Err(e) => {
+			eprintln!("Unable to listen on {}: {e}", path.display());
+			return Err(Stop::Exit(ERR_COMMAND_FAILED));
+		},
+	};
+	// anyone able to connect can control the player
+	std::fs::set_permissions(path, owner_only()).unwrap();
+	let server = format!("{}:{}", command.common_args().url_host(), command.common_args().port());
+	// notified by the connection that finds the token rejected
+	let rejected = std::rc::Rc::new(tokio::sync::Notify::new());
+	// `main_logic` isn't `Send`, so connections are served concurrently on this thread
+	tokio::task::LocalSet::new().run_until(async {
+		loop {
+			tokio::select! {
+				accepted = listener.accept() => match accepted {
+					Ok((stream, _)) => {
+						let connection = serve_daemon_connection(stream, client.clone(), token.to_string(), server.clone(), rejected.clone());
+						tokio::task::spawn_local(connection);
+					},
+					Err(e) => eprintln!("Unable to accept a connection: {e}"),
+				},
+				_ = rejected.notified() => break,
+			}
+		}
+	}).await;
+	let _ = std::fs::remove_file(path);
+	Err(Stop::TokenRejected)
+}
+
+async fn serve_daemon_connection(
+	stream: tokio::net::UnixStream, client: reqwest::Client, token: String, server: String, rejected: std::rc::Rc<tokio::sync::Notify>,
+) {
+	use tokio::io::{AsyncBufReadExt as _, AsyncWriteExt as _};
+	let (reader, mut writer) = stream.into_split();
+	let mut lines = tokio::io::BufReader::new(reader).lines();
+	// the client first names the server it means, so that one meaning another server can run its command directly
+	let reply = match lines.next_line().await {
+		Ok(Some(named)) if named.trim() == server => DAEMON_SAME_SERVER,
+		Ok(Some(_)) => DAEMON_OTHER_SERVER,
+		_ => return,
+	};
+	if writer.write_all(format!("{reply}\n").as_bytes()).await.is_err() || reply != DAEMON_SAME_SERVER {
+		return;
+	}
+	while let Ok(Some(line)) = lines.next_line().await {
+		let code = match parse_script_line(&line, &server) {
+			Ok(line_command) if !line_command.runs_in_daemon() => {
+				eprintln!("`{line}` prints its results, so it needs to be run directly");
+				ERR_INVALID_ARGS
+			},
+			// waiting one out here would leave the daemon, rather than whoever asked for it, to cancel it
+			Ok(line_command) if line_command.get_delay().and_then(timefmt::parse_delay).is_some_and(|delay| !delay.is_zero()) => {
+				eprintln!("`{line}` has a delay; delays are waited out before commands are handed to the daemon");
+				ERR_INVALID_ARGS
+			},
+			Ok(Command::Shuffle(BaseArgs { yes: false, .. })) => {
+				eprintln!("`{line}` needs --yes; `shuffle` is confirmed before it's handed to the daemon");
+				ERR_NOT_CONFIRMED
+			},
+			Ok(line_command) => match run_in_process(line_command, &client, &token).await {
+				Ok(()) => 0,
+				Err(Stop::TokenRejected) => {
+					eprintln!("The token was rejected, stopping the daemon");
+					let _ = writer.write_all(format!("{ERR_COMMAND_FAILED}\n").as_bytes()).await;
+					rejected.notify_one();
+					return;
+				},
+				Err(stop) => stop.code(),
+			},
+			Err(e) => {
+				eprintln!("{e}");
+				ERR_INVALID_ARGS
+			},
+		};
+		if writer.write_all(format!("{code}\n").as_bytes()).await.is_err() {
+			break;
+		}
+	}
+}
+
+// Where `watch` writes its lines.
+enum WatchOutput {
+	Stdout,
+	// Opened lazily, and again after the reader goes away, so a restarting status bar doesn't take `watch` down with it.
+	Fifo { path: PathBuf, pipe: Option<std::fs::File> },
+}
+
+impl WatchOutput {
+	async fn write_line(&mut self, line: &str) -> Outcome {
+		match self {
+			WatchOutput::Stdout => {
+				let mut stdout = std::io::stdout().lock();
+				if writeln!(stdout, "{line}").and_then(|_| stdout.flush()).is_err() {
+					// whatever was reading the output has exited, so there's no one left to watch for
+					return Err(Stop::Exit(0));
+				}
+				Ok(())
+			},
+			WatchOutput::Fifo { path, pipe } => loop {
+				if pipe.is_none() {
+					let open_path = path.clone();
+					// opening a FIFO for writing blocks until something opens it for reading
+					let opened = tokio::task::spawn_blocking(move || std::fs::OpenOptions::new().write(true).open(open_path)).await.unwrap();
+					match opened {
+						Ok(file) => *pipe = Some(file),
+						Err(e) => {
+							eprintln!("Unable to open {}: {e}", path.display());
+							return Err(Stop::Exit(ERR_COMMAND_FAILED));
+						},
+					}
+				}
+				match writeln!(pipe.as_mut().unwrap(), "{line}") {
+					Ok(()) => return Ok(()),
+					// the reader went away; wait for the next one and give it the line instead
+					Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => *pipe = None,
+					Err(e) => {
+						eprintln!("Unable to write to {}: {e}", path.display());
+						return Err(Stop::Exit(ERR_COMMAND_FAILED));
+					},
+				}
+			},
+		}
+	}
+}
+
+fn watch_line(state: &StateResponse) -> String {
+	match &state.video {
+		Some(video) => format!("{:#}: {} - {}", state.player.track_state, video.author, video.title),
+		None => String::from("Nothing playing"),
+	}
+}
+
+// Polls the state and writes a line each time it changes. A FIFO gets the line again on every poll,
+// since a reader that's only just opened it would otherwise have nothing to show until the song changes.
+async fn watch(client: &reqwest::Client, command: &Command, token: &str, mut output: WatchOutput) -> Outcome {
+	let mut last_line = None;
+	loop {
+		let polled_at = tokio::time::Instant::now();
+		let body = get_state(client, command, token).await?;
+		let Ok(state) = serde_json::from_str::<StateResponse>(&body) else {
+			eprintln!("Unexpected state response from YTMD");
+			return Err(Stop::Exit(ERR_COMMAND_FAILED));
+		};
+		let line = watch_line(&state);
+		if last_line.as_ref() != Some(&line) || matches!(output, WatchOutput::Fifo { .. }) {
+			output.write_line(&line).await?;
+			last_line = Some(line);
+		}
+		tokio::time::sleep_until(polled_at + STATE_POLL_INTERVAL).await;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use mock::{MockServer, Response};
+	use tokenstore::MemoryTokenStore;
+
+	fn play_against(server: &MockServer) -> Command {
+		let port = server.port.to_string();
+		Command::from_args(["play", "--server", "127.0.0.1", "--port", &port]).ok().unwrap()
+	}
+
+	// Stands in for YTMD granting a token, then answers commands with `command_status`.
+	fn authorizing_server(token_status: u16, command_status: u16) -> MockServer {
+		MockServer::start(move |request| match &*request.path {
+			"/api/v1/auth/requestcode" => Response::json(200, r#"{"code":"1234"}"#),
+			"/api/v1/auth/request" => Response::json(token_status, r#"{"token":"new-token"}"#),
+			_ if command_status == 401 => Response::json(401, r#"{"error":"UNAUTHORIZED"}"#),
+			_ => Response::json(command_status, "{}"),
+		})
+	}
+
+	#[tokio::test]
+	async fn a_rejected_token_is_removed_from_the_store() {
+		let server = MockServer::start(|_| Response::json(401, r#"{"error":"UNAUTHORIZED"}"#));
+		let command = play_against(&server);
+		let store = MemoryTokenStore::default();
+		store.set(&command.get_token_key(), "old-token").unwrap();
+		store.set("other-host", "other-token").unwrap();
+		let client = build_client(&command).await;
+		assert!(matches!(run_authorized(command, client, &store).await, Ok(None)));
+		assert_eq!(store.servers(), ["other-host"]);
+	}
+
+	#[tokio::test]
+	async fn a_new_token_is_stored_once_it_has_been_used() {
+		let server = authorizing_server(200, 200);
+		let command = play_against(&server);
+		let key = command.get_token_key();
+		let store = MemoryTokenStore::default();
+		let client = build_client(&command).await;
+		assert!(matches!(run_authorized(command, client, &store).await, Ok(None)));
+		assert_eq!(store.get(&key).as_deref(), Some("new-token"));
+		assert_eq!(server.requests().last().unwrap().headers.get("authorization").map(String::as_str), Some("new-token"));
+	}
+
+	#[tokio::test]
+	async fn a_new_token_is_not_stored_unless_authorization_succeeds() {
+		let store = MemoryTokenStore::default();
+		let server = authorizing_server(403, 200);
+		let command = play_against(&server);
+		let client = build_client(&command).await;
+		assert!(matches!(run_authorized(command, client, &store).await, Ok(Some("authorization denied"))));
+		assert!(store.servers().is_empty());
+
+		// granted, but rejected as soon as it's used
+		let server = authorizing_server(200, 401);
+		let command = play_against(&server);
+		let client = build_client(&command).await;
+		assert!(matches!(run_authorized(command, client, &store).await, Ok(None)));
+		assert!(store.servers().is_empty());
+	}
+}
@@ -0,0 +1,106 @@
+// A canned-response stand-in for the YTMD companion server, for the unit tests in `main.rs`
+// and, through `tests/common`, the integration tests.
+#![allow(dead_code)]
+
+use std::{
+	collections::HashMap,
+	io::{BufRead, BufReader, Read, Write},
+	net::TcpListener,
+	sync::{Arc, Mutex},
+	time::Instant,
+};
+
+#[derive(Debug, Clone)]
+pub struct Request {
+	pub method: String,
+	pub path: String,
+	pub headers: HashMap<String, String>,
+	pub body: String,
+	// when the request had been read in full
+	pub at: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+	pub status: u16,
+	pub headers: Vec<(String, String)>,
+	pub body: String,
+}
+
+impl Response {
+	pub fn json(status: u16, body: &str) -> Self {
+		Response {
+			status,
+			headers: vec![("content-type".into(), "application/json".into())],
+			body: body.to_string(),
+		}
+	}
+	pub fn with_header(mut self, key: &str, value: &str) -> Self {
+		self.headers.push((key.to_string(), value.to_string()));
+		self
+	}
+}
+
+pub struct MockServer {
+	pub port: u16,
+	requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+	// Serves every request with `handler` until the test process exits.
+	pub fn start(handler: impl Fn(&Request) -> Response + Send + 'static) -> Self {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let port = listener.local_addr().unwrap().port();
+		let requests = Arc::new(Mutex::new(Vec::new()));
+		let recorded = requests.clone();
+		std::thread::spawn(move || {
+			for stream in listener.incoming() {
+				let Ok(mut stream) = stream else { continue };
+				let Some(request) = read_request(&mut stream) else { continue };
+				let response = handler(&request);
+				recorded.lock().unwrap().push(request);
+				let mut head = format!("HTTP/1.1 {} Mock\r\ncontent-length: {}\r\nconnection: close\r\n", response.status, response.body.len());
+				for (key, value) in &response.headers {
+					head += &format!("{key}: {value}\r\n");
+				}
+				head += "\r\n";
+				let _ = stream.write_all(head.as_bytes());
+				let _ = stream.write_all(response.body.as_bytes());
+			}
+		});
+		MockServer { port, requests }
+	}
+
+	// What ytmdctrl stores this server's token under.
+	pub fn addr(&self) -> String {
+		format!("127.0.0.1:{}", self.port)
+	}
+
+	pub fn requests(&self) -> Vec<Request> {
+		self.requests.lock().unwrap().clone()
+	}
+}
+
+fn read_request(stream: &mut std::net::TcpStream) -> Option<Request> {
+	let mut reader = BufReader::new(stream);
+	let mut line = String::new();
+	reader.read_line(&mut line).ok()?;
+	let mut parts = line.split_whitespace();
+	let method = parts.next()?.to_string();
+	let path = parts.next()?.to_string();
+	let mut headers = HashMap::new();
+	loop {
+		let mut line = String::new();
+		reader.read_line(&mut line).ok()?;
+		let line = line.trim_end();
+		if line.is_empty() {
+			break;
+		}
+		let (key, value) = line.split_once(':')?;
+		headers.insert(key.trim().to_lowercase(), value.trim().to_string());
+	}
+	let len = headers.get("content-length").and_then(|l| l.parse().ok()).unwrap_or(0);
+	let mut body = vec![0; len];
+	reader.read_exact(&mut body).ok()?;
+	Some(Request { method, path, headers, body: String::from_utf8(body).ok()?, at: Instant::now() })
+}
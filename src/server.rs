@@ -0,0 +1,116 @@
+use std::{net::Ipv6Addr, str::FromStr};
+
+// The `--server` option: a host, optionally with a port, as typed or pasted by the user.
+// URLs like `http://host:1234/` are accepted and reduced to their host and port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerAddr {
+	pub host: String,
+	pub port: Option<u16>,
+}
+
+impl ServerAddr {
+	pub fn localhost() -> Self {
+		ServerAddr { host: String::from("localhost"), port: None }
+	}
+
+	// The host as it goes in a URL, with IPv6 addresses bracketed.
+	pub fn url_host(&self) -> String {
+		if self.host.contains(':') {
+			format!("[{}]", self.host)
+		} else {
+			self.host.clone()
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidServerAddr;
+
+impl FromStr for ServerAddr {
+	type Err = InvalidServerAddr;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let s = s.trim();
+		let s = s.split_once("://").map_or(s, |(_, rest)| rest);
+		let s = s.split(['/', '?', '#']).next().unwrap_or_default();
+
+		let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+			// `[v6]` or `[v6]:port`
+			let (host, rest) = rest.split_once(']').ok_or(InvalidServerAddr)?;
+			let port = match rest {
+				"" => None,
+				rest => Some(rest.strip_prefix(':').ok_or(InvalidServerAddr)?),
+			};
+			host.parse::<Ipv6Addr>().map_err(|_| InvalidServerAddr)?;
+			(host, port)
+		} else if s.parse::<Ipv6Addr>().is_ok() {
+			// a bare IPv6 address has too many colons to carry a port
+			(s, None)
+		} else {
+			match s.split_once(':') {
+				Some((host, port)) => (host, Some(port)),
+				None => (s, None),
+			}
+		};
+
+		let port = match port {
+			Some(port) => Some(port.parse::<u16>().ok().filter(|&p| p != 0).ok_or(InvalidServerAddr)?),
+			None => None,
+		};
+		let valid_name = !host.is_empty()
+			&& !host.starts_with(['-', '.'])
+			&& host.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'));
+		if !valid_name && host.parse::<Ipv6Addr>().is_err() {
+			return Err(InvalidServerAddr);
+		}
+		Ok(ServerAddr { host: host.to_string(), port })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(s: &str) -> Option<(String, Option<u16>)> {
+		s.parse::<ServerAddr>().ok().map(|addr| (addr.host, addr.port))
+	}
+
+	#[test]
+	fn bare_host() {
+		assert_eq!(parse("localhost"), Some(("localhost".into(), None)));
+		assert_eq!(parse("192.168.1.42"), Some(("192.168.1.42".into(), None)));
+		assert_eq!(parse("media-pc.local"), Some(("media-pc.local".into(), None)));
+	}
+
+	#[test]
+	fn host_with_port() {
+		assert_eq!(parse("host:1234"), Some(("host".into(), Some(1234))));
+		assert_eq!(parse("[::1]:1234"), Some(("::1".into(), Some(1234))));
+		assert_eq!(parse("::1"), Some(("::1".into(), None)));
+	}
+
+	#[test]
+	fn urls_are_reduced_to_host_and_port() {
+		assert_eq!(parse("http://host"), Some(("host".into(), None)));
+		assert_eq!(parse("http://host/"), Some(("host".into(), None)));
+		assert_eq!(parse("https://host:9863/api/v1/state?x=1"), Some(("host".into(), Some(9863))));
+		assert_eq!(parse("http://[fe80::1]:9863/"), Some(("fe80::1".into(), Some(9863))));
+	}
+
+	#[test]
+	fn invalid_hosts_are_rejected() {
+		assert_eq!(parse(""), None);
+		assert_eq!(parse("http://"), None);
+		assert_eq!(parse("host:"), None);
+		assert_eq!(parse("host:99999"), None);
+		assert_eq!(parse("host:abc"), None);
+		assert_eq!(parse("my host"), None);
+		assert_eq!(parse("user@host"), None);
+		assert_eq!(parse("[not-v6]:80"), None);
+	}
+
+	#[test]
+	fn ipv6_hosts_are_bracketed_in_urls() {
+		assert_eq!("::1".parse::<ServerAddr>().unwrap().url_host(), "[::1]");
+		assert_eq!("host".parse::<ServerAddr>().unwrap().url_host(), "host");
+	}
+}
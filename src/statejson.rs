@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
 #[derive(Debug, Clone, Serialize_repr, Deserialize_repr)]
@@ -38,6 +39,105 @@ pub enum VideoType {
 	Podcast = 3,
 }
 
+// The enums display as a symbol and a name for people (`▶ Playing`), or with `{:#}` as the name alone.
+// `to_script_str` gives a stable lowercase name for scripts instead (`playing`).
+fn write_label(f: &mut std::fmt::Formatter<'_>, symbol: Option<&str>, name: &str) -> std::fmt::Result {
+	match symbol {
+		Some(symbol) if !f.alternate() => write!(f, "{symbol} {name}"),
+		_ => f.write_str(name),
+	}
+}
+
+impl PlaybackState {
+	pub fn to_script_str(&self) -> &'static str {
+		match self {
+			PlaybackState::Unknown => "unknown",
+			PlaybackState::Paused => "paused",
+			PlaybackState::Playing => "playing",
+			PlaybackState::Buffering => "buffering",
+		}
+	}
+}
+
+impl std::fmt::Display for PlaybackState {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			PlaybackState::Unknown => write_label(f, Some("?"), "Unknown"),
+			PlaybackState::Paused => write_label(f, Some("⏸"), "Paused"),
+			PlaybackState::Playing => write_label(f, Some("▶"), "Playing"),
+			PlaybackState::Buffering => write_label(f, Some("…"), "Buffering"),
+		}
+	}
+}
+
+impl RepeatMode {
+	pub fn to_script_str(&self) -> &'static str {
+		match self {
+			RepeatMode::Unknown => "unknown",
+			RepeatMode::None => "repeat_none",
+			RepeatMode::All => "repeat_all",
+			RepeatMode::One => "repeat_one",
+		}
+	}
+}
+
+impl std::fmt::Display for RepeatMode {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			RepeatMode::Unknown => write_label(f, Some("?"), "Unknown"),
+			RepeatMode::None => write_label(f, None, "No Repeat"),
+			RepeatMode::All => write_label(f, Some("🔁"), "Repeat All"),
+			RepeatMode::One => write_label(f, Some("🔂"), "Repeat One"),
+		}
+	}
+}
+
+impl LikeState {
+	pub fn to_script_str(&self) -> &'static str {
+		match self {
+			LikeState::Unknown => "unknown",
+			LikeState::Dislike => "dislike",
+			LikeState::Indifferent => "indifferent",
+			LikeState::Like => "like",
+		}
+	}
+}
+
+impl std::fmt::Display for LikeState {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			LikeState::Unknown => write_label(f, Some("?"), "Unknown"),
+			LikeState::Dislike => write_label(f, Some("👎"), "Disliked"),
+			LikeState::Indifferent => write_label(f, None, "Indifferent"),
+			LikeState::Like => write_label(f, Some("👍"), "Liked"),
+		}
+	}
+}
+
+impl VideoType {
+	pub fn to_script_str(&self) -> &'static str {
+		match self {
+			VideoType::Unknown => "unknown",
+			VideoType::Audio => "audio",
+			VideoType::Video => "video",
+			VideoType::Uploaded => "uploaded",
+			VideoType::Podcast => "podcast",
+		}
+	}
+}
+
+impl std::fmt::Display for VideoType {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			VideoType::Unknown => write_label(f, Some("?"), "Unknown"),
+			VideoType::Audio => write_label(f, None, "Audio"),
+			VideoType::Video => write_label(f, None, "Video"),
+			VideoType::Uploaded => write_label(f, None, "Uploaded"),
+			VideoType::Podcast => write_label(f, None, "Podcast"),
+		}
+	}
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StateResponse<'a> {
 	pub player: PlayerState<'a>,
@@ -53,6 +153,7 @@ pub struct PlayerState<'a> {
 	#[serde(rename = "videoProgress")]
 	pub video_progress: f32,
 	pub volume: u8,
+	pub muted: Option<bool>,
 	#[serde(rename = "adPlaying")]
 	pub ad_playing: bool,
 	#[serde(borrow)]
@@ -116,4 +217,80 @@ pub struct ThumbnailState<'a> {
 	pub url: &'a str,
 	pub width: u32,
 	pub height: u32,
-}
\ No newline at end of file
+}
+
+// Short names for fields whose JSON key doesn't follow from the snake_case name.
+const FIELD_ALIASES: &[(&str, &str)] = &[
+	("selected_index", "selectedItemIndex"),
+	("progress", "videoProgress"),
+	("state", "trackState"),
+	("duration", "durationSeconds"),
+];
+
+// Looks up a dotted path such as `video.title` in a state response.
+//
+// Segments may be given in snake_case, array elements are selected by index, and
+// the fields of `player` can be used without the `player.` prefix (`volume`, `queue.items.0.title`).
+// A `null` value is treated as missing.
+pub fn select_field<'v>(state: &'v Value, path: &str) -> Option<&'v Value> {
+	fn child<'v>(value: &'v Value, segment: &str) -> Option<&'v Value> {
+		match value {
+			Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+			Value::Object(map) => map.get(segment)
+				.or_else(|| map.get(&snake_to_camel(segment)))
+				.or_else(|| FIELD_ALIASES.iter()
+					.find(|(alias, _)| *alias == segment)
+					.and_then(|(_, key)| map.get(*key))),
+			_ => None,
+		}
+	}
+	let mut segments = path.split('.');
+	let first = segments.next()?;
+	let mut value = child(state, first).or_else(|| child(state.get("player")?, first))?;
+	for segment in segments {
+		value = child(value, segment)?;
+	}
+	Some(value).filter(|v| !v.is_null())
+}
+
+fn snake_to_camel(s: &str) -> String {
+	let mut out = String::with_capacity(s.len());
+	let mut upper = false;
+	for c in s.chars() {
+		if c == '_' {
+			upper = true;
+		} else if upper {
+			out.extend(c.to_uppercase());
+			upper = false;
+		} else {
+			out.push(c);
+		}
+	}
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn script_strings_are_stable() {
+		let playback = [PlaybackState::Unknown, PlaybackState::Paused, PlaybackState::Playing, PlaybackState::Buffering];
+		assert_eq!(playback.map(|s| s.to_script_str()), ["unknown", "paused", "playing", "buffering"]);
+		let repeat = [RepeatMode::Unknown, RepeatMode::None, RepeatMode::All, RepeatMode::One];
+		assert_eq!(repeat.map(|s| s.to_script_str()), ["unknown", "repeat_none", "repeat_all", "repeat_one"]);
+		let like = [LikeState::Unknown, LikeState::Dislike, LikeState::Indifferent, LikeState::Like];
+		assert_eq!(like.map(|s| s.to_script_str()), ["unknown", "dislike", "indifferent", "like"]);
+		let video = [VideoType::Unknown, VideoType::Audio, VideoType::Video, VideoType::Uploaded, VideoType::Podcast];
+		assert_eq!(video.map(|s| s.to_script_str()), ["unknown", "audio", "video", "uploaded", "podcast"]);
+	}
+
+	#[test]
+	fn labels_can_leave_out_the_symbol() {
+		assert_eq!(PlaybackState::Playing.to_string(), "▶ Playing");
+		assert_eq!(format!("{:#}", PlaybackState::Playing), "Playing");
+		assert_eq!(RepeatMode::All.to_string(), "🔁 Repeat All");
+		assert_eq!(format!("{:#}", RepeatMode::All), "Repeat All");
+		assert_eq!(LikeState::Indifferent.to_string(), "Indifferent");
+	}
+}
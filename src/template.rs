@@ -0,0 +1,176 @@
+use serde_json::Value;
+
+use crate::statejson::select_field;
+
+// A parsed `--template`. Placeholders are `{path}`, looked up like `state --field` does (`{video.title}`, `{volume}`),
+// and `{#path}...{/path}` repeats its contents for each element of the array at `path` (`{#queue.items}{title}{/queue.items}`).
+// Inside a block, paths are looked up in the element first, and `{index}` is its position. `{{` and `}}` are literal braces.
+#[derive(Debug)]
+pub struct Template {
+	nodes: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+	Text(String),
+	Field(String),
+	Block(String, Vec<Node>),
+}
+
+impl Template {
+	pub fn parse(source: &str) -> Result<Template, String> {
+		let mut rest = source;
+		let nodes = parse_nodes(&mut rest, None)?;
+		Ok(Template { nodes })
+	}
+
+	pub fn render(&self, state: &Value) -> String {
+		let mut out = String::new();
+		render_nodes(&self.nodes, &mut vec![Scope { value: state, index: None }], &mut out);
+		out
+	}
+}
+
+// Parses until the end of `rest`, or until the `{/name}` closing `block` if it's given.
+fn parse_nodes(rest: &mut &str, block: Option<&str>) -> Result<Vec<Node>, String> {
+	let mut nodes = Vec::new();
+	let mut text = String::new();
+	loop {
+		let Some(idx) = rest.find(['{', '}']) else {
+			text.push_str(rest);
+			*rest = "";
+			break;
+		};
+		text.push_str(&rest[..idx]);
+		let tail = &rest[idx..];
+		if let Some(after) = tail.strip_prefix("{{") {
+			text.push('{');
+			*rest = after;
+			continue;
+		}
+		if let Some(after) = tail.strip_prefix("}}") {
+			text.push('}');
+			*rest = after;
+			continue;
+		}
+		if tail.starts_with('}') {
+			return Err(String::from("unmatched `}`; write `}}` for a literal brace"));
+		}
+		let Some(end) = tail.find('}') else {
+			return Err(String::from("unclosed `{`; write `{{` for a literal brace"));
+		};
+		let tag = tail[1..end].trim();
+		*rest = &tail[end + 1..];
+		if !text.is_empty() {
+			nodes.push(Node::Text(std::mem::take(&mut text)));
+		}
+		if let Some(name) = tag.strip_prefix('#') {
+			let children = parse_nodes(rest, Some(name))?;
+			nodes.push(Node::Block(name.to_string(), children));
+		} else if let Some(name) = tag.strip_prefix('/') {
+			return match block {
+				Some(open) if open == name => Ok(nodes),
+				Some(open) => Err(format!("`{{/{name}}}` closes `{{#{open}}}`")),
+				None => Err(format!("`{{/{name}}}` closes nothing")),
+			};
+		} else if tag.is_empty() {
+			return Err(String::from("empty placeholder `{}`"));
+		} else {
+			nodes.push(Node::Field(tag.to_string()));
+		}
+	}
+	if !text.is_empty() {
+		nodes.push(Node::Text(text));
+	}
+	match block {
+		Some(open) => Err(format!("`{{#{open}}}` is never closed")),
+		None => Ok(nodes),
+	}
+}
+
+struct Scope<'v> {
+	value: &'v Value,
+	index: Option<usize>,
+}
+
+fn lookup<'v>(scopes: &[Scope<'v>], path: &str) -> Option<Value> {
+	for scope in scopes.iter().rev() {
+		if path == "index" {
+			if let Some(index) = scope.index {
+				return Some(Value::from(index));
+			}
+		}
+		if let Some(value) = select_field(scope.value, path) {
+			return Some(value.clone());
+		}
+	}
+	None
+}
+
+fn render_nodes<'v>(nodes: &[Node], scopes: &mut Vec<Scope<'v>>, out: &mut String) {
+	for node in nodes {
+		match node {
+			Node::Text(text) => out.push_str(text),
+			Node::Field(path) => match lookup(scopes, path) {
+				Some(Value::String(s)) => out.push_str(&s),
+				Some(value) => out.push_str(&value.to_string()),
+				None => (),
+			},
+			Node::Block(path, children) => {
+				let Some(items) = scopes.iter().rev().find_map(|scope| select_field(scope.value, path)) else {
+					continue;
+				};
+				let Value::Array(items) = items else {
+					continue;
+				};
+				for (index, item) in items.iter().enumerate() {
+					scopes.push(Scope { value: item, index: Some(index) });
+					render_nodes(children, scopes, out);
+					scopes.pop();
+				}
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn state() -> Value {
+		serde_json::json!({
+			"player": {
+				"volume": 50,
+				"queue": { "items": [{ "title": "One" }, { "title": "Two" }] },
+			},
+			"video": { "title": "Two", "author": "Someone" },
+		})
+	}
+
+	#[test]
+	fn fields_are_substituted() {
+		let template = Template::parse("{video.author} - {video.title} ({volume}%){missing}").unwrap();
+		assert_eq!(template.render(&state()), "Someone - Two (50%)");
+	}
+
+	#[test]
+	fn blocks_repeat_for_each_item() {
+		let template = Template::parse("{#queue.items}{index}: {title} by {video.author}\n{/queue.items}").unwrap();
+		assert_eq!(template.render(&state()), "0: One by Someone\n1: Two by Someone\n");
+	}
+
+	#[test]
+	fn braces_can_be_escaped() {
+		let template = Template::parse("{{{volume}}}").unwrap();
+		assert_eq!(template.render(&state()), "{50}");
+	}
+
+	#[test]
+	fn malformed_templates_are_rejected() {
+		assert!(Template::parse("{video.title").is_err());
+		assert!(Template::parse("}").is_err());
+		assert!(Template::parse("{#queue.items}{title}").is_err());
+		assert!(Template::parse("{#a}{/b}").is_err());
+		assert!(Template::parse("{/a}").is_err());
+	}
+}
@@ -0,0 +1,153 @@
+use std::{str::FromStr, time::Duration};
+
+use chrono::{Local, NaiveTime};
+
+// Parses `ss`, `mm:ss` or `hh:mm:ss` into seconds. Seconds may be fractional (`1:05.5`).
+pub fn parse_duration_hms(s: &str) -> Option<f32> {
+	let mut parts = s.trim().rsplit(':');
+	let seconds: f32 = parts.next()?.parse().ok()?;
+	if !seconds.is_finite() || seconds < 0.0 {
+		return None;
+	}
+	let mut total = seconds;
+	for (parts_seen, part) in parts.enumerate() {
+		// only minutes and hours are allowed above seconds
+		if parts_seen >= 2 {
+			return None;
+		}
+		let value: u32 = part.parse().ok()?;
+		total += value as f32 * 60f32.powi(parts_seen as i32 + 1);
+	}
+	Some(total)
+}
+
+// Formats seconds as `m:ss`, or `h:mm:ss` past an hour.
+// Always rounds down, like a player's clock does, so positions and durations shown together agree.
+pub fn format_duration(secs: f32) -> String {
+	let total = secs.max(0.0).floor() as u64;
+	let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+	if hours > 0 {
+		format!("{hours}:{minutes:02}:{seconds:02}")
+	} else {
+		format!("{minutes}:{seconds:02}")
+	}
+}
+
+// A song's duration as YTMD reports it, if it means anything; live streams and songs still loading report `0`.
+// Anything working from a song's duration should go through this rather than divide by or count down from it.
+pub fn usable_duration(secs: f32) -> Option<f32> {
+	(secs.is_finite() && secs > 0.0).then_some(secs)
+}
+
+// What to show in place of a duration that isn't usable.
+pub fn unknown_duration(is_live: bool) -> &'static str {
+	if is_live { "live" } else { "--:--" }
+}
+
+// A point in a song, given on the command line as `ss`, `mm:ss` or `hh:mm:ss`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timestamp(pub f32);
+
+impl FromStr for Timestamp {
+	type Err = ();
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		parse_duration_hms(s).map(Timestamp).ok_or(())
+	}
+}
+
+// Parses a `--delay`: a bare number of seconds (`5`, `2.5`), a duration `parse_duration` understands (`30s`, `1h30m`),
+// or `until HH:MM[:SS]`, meaning the next time the local clock reads that time.
+pub fn parse_delay(delay: &str) -> Option<Duration> {
+	let delay = delay.trim();
+	if let Ok(seconds) = delay.parse::<f64>() {
+		return Duration::try_from_secs_f64(seconds).ok();
+	}
+	match delay.strip_prefix("until") {
+		Some(time) => until_local_time(time.trim()),
+		None => parse_duration::parse(delay).ok(),
+	}
+}
+
+fn until_local_time(time: &str) -> Option<Duration> {
+	let time = NaiveTime::parse_from_str(time, "%H:%M:%S")
+		.or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+		.ok()?;
+	let now = Local::now();
+	let mut date = now.date_naive();
+	// today if it's still ahead, otherwise tomorrow (or the day after, if a DST change skips it)
+	for _ in 0..3 {
+		if let Some(target) = date.and_time(time).and_local_timezone(Local).earliest() {
+			if target > now {
+				return (target - now).to_std().ok();
+			}
+		}
+		date = date.succ_opt()?;
+	}
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn durations_are_formatted_like_a_clock() {
+		assert_eq!(format_duration(0.0), "0:00");
+		assert_eq!(format_duration(59.0), "0:59");
+		assert_eq!(format_duration(65.9), "1:05");
+		assert_eq!(format_duration(3600.0), "1:00:00");
+		assert_eq!(format_duration(3725.0), "1:02:05");
+		assert_eq!(format_duration(-3.0), "0:00");
+	}
+
+	#[test]
+	fn unusable_durations_are_caught() {
+		assert_eq!(usable_duration(245.0), Some(245.0));
+		assert_eq!(usable_duration(0.0), None);
+		assert_eq!(usable_duration(f32::NAN), None);
+		assert_eq!(usable_duration(f32::INFINITY), None);
+		assert_eq!(usable_duration(-1.0), None);
+	}
+
+	#[test]
+	fn hms_durations_are_parsed() {
+		assert_eq!(parse_duration_hms("0"), Some(0.0));
+		assert_eq!(parse_duration_hms("45"), Some(45.0));
+		assert_eq!(parse_duration_hms("3:21"), Some(201.0));
+		assert_eq!(parse_duration_hms("1:02:05"), Some(3725.0));
+		assert_eq!(parse_duration_hms("1:05.5"), Some(65.5));
+		assert_eq!(parse_duration_hms("1:2:3:4"), None);
+		assert_eq!(parse_duration_hms("-5"), None);
+		assert_eq!(parse_duration_hms("1:xx"), None);
+	}
+
+	#[test]
+	fn formatting_round_trips_whole_seconds() {
+		for secs in [0.0, 7.0, 201.0, 3725.0] {
+			assert_eq!(parse_duration_hms(&format_duration(secs)), Some(secs));
+		}
+	}
+
+	#[test]
+	fn delay_accepts_durations() {
+		assert_eq!(parse_delay("1m30s"), Some(Duration::from_secs(90)));
+		assert_eq!(parse_delay("nonsense"), None);
+	}
+
+	#[test]
+	fn delay_accepts_bare_seconds() {
+		assert_eq!(parse_delay("5"), Some(Duration::from_secs(5)));
+		assert_eq!(parse_delay("2.5"), Some(Duration::from_millis(2500)));
+		assert_eq!(parse_delay("-5"), None);
+		assert_eq!(parse_delay("inf"), None);
+	}
+
+	#[test]
+	fn delay_until_is_within_a_day() {
+		let delay = parse_delay("until 23:30").unwrap();
+		assert!(delay > Duration::ZERO && delay <= Duration::from_secs(25 * 3600));
+		assert!(parse_delay("until 07:15:30").is_some());
+		assert_eq!(parse_delay("until 25:00"), None);
+		assert_eq!(parse_delay("until"), None);
+	}
+}
@@ -0,0 +1,145 @@
+use std::{collections::HashMap, fs::Permissions, io::Write as _, path::PathBuf};
+
+// Where the tokens YTMD hands out are kept between runs, one for each server address.
+pub trait TokenStore {
+	fn get(&self, server: &str) -> Option<String>;
+	fn set(&self, server: &str, token: &str) -> std::io::Result<()>;
+	fn remove(&self, server: &str) -> std::io::Result<()>;
+	// Every server there's a token for, sorted.
+	fn servers(&self) -> Vec<String>;
+	// Where the tokens are kept, for messages about failing to change them.
+	fn location(&self) -> String;
+}
+
+// The store ytmdctrl normally uses: a JSON object of server -> token, readable only by its owner.
+pub struct FileTokenStore {
+	path: PathBuf,
+}
+
+impl FileTokenStore {
+	pub fn new(path: PathBuf) -> Self {
+		FileTokenStore { path }
+	}
+
+	fn read(&self) -> HashMap<String, String> {
+		std::fs::read_to_string(&self.path).ok()
+			.and_then(|contents| serde_json::from_str(&contents).ok())
+			.unwrap_or_default()
+	}
+
+	// Changes the store under an exclusive lock, so that runs changing it at the same time don't
+	// undo each other's changes, and replaces it atomically, so that a run reading it never sees it half written.
+	fn update(&self, change: impl FnOnce(&mut HashMap<String, String>)) -> std::io::Result<()> {
+		std::fs::create_dir_all(self.path.parent().unwrap())?;
+		// the store itself is replaced rather than written to, so the lock lives in a file of its own
+		let lock = std::fs::OpenOptions::new().write(true).create(true).truncate(false).open(self.path.with_extension("lock"))?;
+		lock.lock()?;
+		let mut store = self.read();
+		change(&mut store);
+		let temp_path = self.path.with_extension("tkn.tmp");
+		let mut temp = std::fs::File::create(&temp_path)?;
+		temp.set_permissions(owner_only())?;
+		temp.write_all(&serde_json::to_vec(&store).unwrap())?;
+		temp.sync_all()?;
+		std::fs::rename(&temp_path, &self.path)
+	}
+}
+
+impl TokenStore for FileTokenStore {
+	fn get(&self, server: &str) -> Option<String> {
+		self.read().remove(server)
+	}
+
+	fn set(&self, server: &str, token: &str) -> std::io::Result<()> {
+		self.update(|store| { store.insert(server.to_string(), token.to_string()); })
+	}
+
+	fn remove(&self, server: &str) -> std::io::Result<()> {
+		self.update(|store| { store.remove(server); })
+	}
+
+	fn servers(&self) -> Vec<String> {
+		let mut servers: Vec<String> = self.read().into_keys().collect();
+		servers.sort_unstable();
+		servers
+	}
+
+	fn location(&self) -> String {
+		self.path.display().to_string()
+	}
+}
+
+// A store that lasts only as long as the process, for testing what's done with tokens without touching the disk.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryTokenStore {
+	tokens: std::sync::Mutex<HashMap<String, String>>,
+}
+
+#[cfg(test)]
+impl TokenStore for MemoryTokenStore {
+	fn get(&self, server: &str) -> Option<String> {
+		self.tokens.lock().unwrap().get(server).cloned()
+	}
+
+	fn set(&self, server: &str, token: &str) -> std::io::Result<()> {
+		self.tokens.lock().unwrap().insert(server.to_string(), token.to_string());
+		Ok(())
+	}
+
+	fn remove(&self, server: &str) -> std::io::Result<()> {
+		self.tokens.lock().unwrap().remove(server);
+		Ok(())
+	}
+
+	fn servers(&self) -> Vec<String> {
+		let mut servers: Vec<String> = self.tokens.lock().unwrap().keys().cloned().collect();
+		servers.sort_unstable();
+		servers
+	}
+
+	fn location(&self) -> String {
+		String::from("memory")
+	}
+}
+
+//TODO: Support non-unix operating systems
+#[cfg(target_family="unix")]
+pub fn owner_only() -> Permissions {
+    use std::os::unix::fs::PermissionsExt as _;
+    Permissions::from_mode(0o600)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Stores a couple of tokens, then drops one, like a rejected token is dropped.
+	fn exercise(store: &dyn TokenStore) {
+		store.set("living-room", "token-a").unwrap();
+		store.set("10.0.0.2", "token-b").unwrap();
+		assert_eq!(store.get("living-room").as_deref(), Some("token-a"));
+		assert_eq!(store.servers(), ["10.0.0.2", "living-room"]);
+		store.remove("living-room").unwrap();
+		assert_eq!(store.get("living-room"), None);
+		assert_eq!(store.get("10.0.0.2").as_deref(), Some("token-b"));
+	}
+
+	#[test]
+	fn memory_store_keeps_tokens_per_server() {
+		exercise(&MemoryTokenStore::default());
+	}
+
+	#[test]
+	fn file_store_keeps_tokens_per_server() {
+		let dir = std::env::temp_dir().join(format!("ytmdctrl-tokenstore-{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&dir);
+		let path = dir.join("ytmdctrl.tkn");
+		exercise(&FileTokenStore::new(path.clone()));
+		use std::os::unix::fs::PermissionsExt as _;
+		assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+		// a fresh store sees what the last one left
+		assert_eq!(FileTokenStore::new(path).servers(), ["10.0.0.2"]);
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
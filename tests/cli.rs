@@ -0,0 +1,56 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn help_lists_every_command_and_option() {
+	let env = TestEnv::with_tokens(&[]);
+	let out = stdout(&env.run(&["--help"]));
+	for command in ["state", "play-pause", "volume", "open", "loop", "switch-version", "restart", "remaining"] {
+		assert!(out.lines().any(|line| line.trim_start().starts_with(command)), "`{command}` missing from:\n{out}");
+	}
+	for option in ["--delay", "--server", "--port", "--script", "--field", "--confirm"] {
+		assert!(out.contains(option), "`{option}` missing from:\n{out}");
+	}
+	assert_eq!(stdout(&env.run(&["help"])), out);
+}
+
+#[test]
+fn command_help_shows_its_arguments() {
+	let env = TestEnv::with_tokens(&[]);
+	let output = env.run(&["seek", "--help"]);
+	assert!(output.status.success());
+	let out = stdout(&output);
+	assert!(out.starts_with("ytmdctrl seek\n"), "{out}");
+	assert!(out.contains("<target>"), "{out}");
+	assert!(out.contains("--force"), "{out}");
+}
+
+#[test]
+fn jumpto_options_are_not_offered_to_volume() {
+	let env = TestEnv::with_tokens(&[]);
+	let out = stdout(&env.run(&["volume", "--help"]));
+	assert!(!out.contains("--force") && !out.contains("--wait-generate"), "{out}");
+	assert_eq!(env.run(&["volume", "30", "--force"]).status.code(), Some(1));
+	assert!(stdout(&env.run(&["jumpto", "--help"])).contains("--wait-generate"));
+}
+
+#[test]
+fn command_specific_options_are_refused_elsewhere() {
+	let env = TestEnv::with_tokens(&[]);
+	for (args, message) in [
+		(&["volume-down", "--max-volume", "80"][..], "`volume-down` has no option `--max-volume`"),
+		(&["volume-up", "--min-volume", "20"], "`volume-up` has no option `--min-volume`"),
+		(&["pause", "--confirm"], "--confirm is only for `mute` and `unmute`"),
+		(&["play-pause", "--yes"], "--yes is only for `shuffle`"),
+		(&["remaining", "--compact"], "--compact is only for `state`"),
+		(&["pause", "--wait-until-playing"], "--wait-until-playing is only for `play`"),
+		(&["state", "--quiet"], "--quiet is only for `is-liked`"),
+	] {
+		let output = env.run(args);
+		assert_eq!(output.status.code(), Some(1), "{args:?}");
+		assert!(stderr(&output).contains(message), "{args:?}: {}", stderr(&output));
+	}
+	assert!(stdout(&env.run(&["volume-up", "--help"])).contains("--max-volume"));
+	assert!(!stdout(&env.run(&["volume-down", "--help"])).contains("--max-volume"));
+}
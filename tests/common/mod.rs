@@ -0,0 +1,145 @@
+// Test helpers: a canned-response stand-in for the YTMD companion server,
+// and a scratch config directory so the token store never touches the real one.
+#![allow(dead_code)]
+
+#[path = "../../src/mock.rs"]
+mod mock;
+
+pub use mock::*;
+
+use std::{
+	collections::HashMap,
+	path::PathBuf,
+	process::{Child, Output, Stdio},
+	sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub const TOKEN: &str = "test-token";
+
+// A throwaway `XDG_CONFIG_HOME` (and `HOME`) for one ytmdctrl invocation.
+pub struct TestEnv {
+	pub dir: PathBuf,
+}
+
+impl TestEnv {
+	// Creates the directory with a token store holding `tokens` (server -> token).
+	pub fn with_tokens(tokens: &[(&str, &str)]) -> Self {
+		static COUNTER: AtomicUsize = AtomicUsize::new(0);
+		let dir = std::env::temp_dir().join(format!(
+			"ytmdctrl-test-{}-{}",
+			std::process::id(),
+			COUNTER.fetch_add(1, Ordering::SeqCst)
+		));
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(dir.join("ytmdctrl")).unwrap();
+		let env = TestEnv { dir };
+		let store: HashMap<_, _> = tokens.iter().copied().collect();
+		std::fs::write(env.token_store_path(), serde_json::to_vec(&store).unwrap()).unwrap();
+		env
+	}
+
+	pub fn write_config(&self, contents: &str) {
+		std::fs::write(self.dir.join("ytmdctrl").join("config.toml"), contents).unwrap();
+	}
+
+	pub fn token_store_path(&self) -> PathBuf {
+		self.dir.join("ytmdctrl").join("ytmdctrl.tkn")
+	}
+
+	pub fn tokens(&self) -> HashMap<String, String> {
+		let contents = std::fs::read_to_string(self.token_store_path()).unwrap();
+		serde_json::from_str(&contents).unwrap_or_default()
+	}
+
+	pub fn run(&self, args: &[&str]) -> Output {
+		std::process::Command::new(env!("CARGO_BIN_EXE_ytmdctrl"))
+			.args(args)
+			.env("XDG_CONFIG_HOME", &self.dir)
+			.env("HOME", &self.dir)
+			.output()
+			.unwrap()
+	}
+
+	// Runs a command against `server` on 127.0.0.1.
+	pub fn run_against(&self, server: &MockServer, args: &[&str]) -> Output {
+		let port = server.port.to_string();
+		let mut full = args.to_vec();
+		full.extend(["--server", "127.0.0.1", "--port", &port]);
+		self.run(&full)
+	}
+
+	// Starts a long-running command against `server`, for tests that need to interact with it while it runs.
+	pub fn spawn_against(&self, server: &MockServer, args: &[&str]) -> Child {
+		std::process::Command::new(env!("CARGO_BIN_EXE_ytmdctrl"))
+			.args(args)
+			.args(["--server", "127.0.0.1", "--port", &server.port.to_string()])
+			.env("XDG_CONFIG_HOME", &self.dir)
+			.env("HOME", &self.dir)
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn()
+			.unwrap()
+	}
+}
+
+impl Drop for TestEnv {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.dir);
+	}
+}
+
+pub fn stdout(output: &Output) -> String {
+	String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+	String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+pub const STATE_JSON: &str = r#"{
+	"player": {
+		"trackState": 1,
+		"videoProgress": 65.5,
+		"volume": 50,
+		"adPlaying": false,
+		"queue": {
+			"autoplay": true,
+			"items": [
+				{ "thumbnails": [], "title": "First Song", "author": "Artist A", "duration": "3:21", "selected": false, "videoId": "vid0", "counterparts": null },
+				{ "thumbnails": [], "title": "Second Song", "author": "Artist B", "duration": "4:05", "selected": true, "videoId": "vid1", "counterparts": null }
+			],
+			"automixItems": [
+				{ "thumbnails": [], "title": "Automix Song", "author": "Artist C", "duration": "2:30", "selected": false, "videoId": "vid2", "counterparts": null }
+			],
+			"isGenerating": false,
+			"isInfinite": false,
+			"repeatMode": 0,
+			"selectedItemIndex": 1
+		}
+	},
+	"video": {
+		"author": "Artist B",
+		"channelId": "chan1",
+		"title": "Second Song",
+		"album": "Some Album",
+		"albumId": "album1",
+		"likeStatus": 1,
+		"thumbnails": [],
+		"durationSeconds": 245,
+		"id": "vid1",
+		"isLive": false,
+		"videoType": 0,
+		"metadataFilled": true
+	},
+	"playlistId": "PL1"
+}"#;
+
+// Answers `state`, `playlists` and `command` the way a healthy YTMD would.
+pub fn ytmd(request: &Request) -> Response {
+	match (&*request.method, &*request.path) {
+		("GET", "/api/v1/state") => Response::json(200, STATE_JSON),
+		("GET", "/api/v1/playlists") => Response::json(200, r#"[{"id":"PL1","title":"Road Trip"},{"id":"PL2","title":"Focus"}]"#),
+		("POST", "/api/v1/command") => Response { status: 204, headers: vec![], body: String::new() },
+		_ => Response::json(404, r#"{"error":"NOT_FOUND"}"#),
+	}
+}
@@ -0,0 +1,1110 @@
+mod common;
+
+use std::io::{BufRead, BufReader};
+
+use common::*;
+
+#[test]
+fn state_is_formatted_for_humans() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["state"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	let out = stdout(&output);
+	assert!(out.contains(r#"Status: ▶ Playing "Second Song""#), "{out}");
+	assert!(out.contains("Progress: 1:05/4:05\n"), "{out}");
+	assert!(out.contains("Volume: 50%"), "{out}");
+	assert!(out.contains("  <0> First Song\n"), "{out}");
+	assert!(out.contains("▶ <1> Second Song <SELECTED>\n"), "{out}");
+	assert!(out.contains("Automix Queue:\n  <A0> Automix Song\n"), "{out}");
+
+	let requests = server.requests();
+	assert_eq!(requests.len(), 1);
+	assert_eq!(requests[0].headers.get("authorization").map(String::as_str), Some(TOKEN));
+}
+
+#[test]
+fn state_marks_the_selected_index_rather_than_item_flags() {
+	let state = STATE_JSON
+		.replace(r#""selectedItemIndex": 1"#, r#""selectedItemIndex": -1"#)
+		.replace(r#""selected": false, "videoId": "vid0""#, r#""selected": true, "videoId": "vid0""#);
+	let server = MockServer::start(move |request| match &*request.path {
+		"/api/v1/state" => Response::json(200, &state),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["state"]);
+	let out = stdout(&output);
+	assert!(out.contains("Queue:\n(no item selected)\n  <0> First Song\n  <1> Second Song\n"), "{out}");
+}
+
+#[test]
+fn state_automix_queue_can_be_left_out_or_shown_alone() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["state", "--no-automix"]);
+	assert!(!stdout(&output).contains("Automix"), "{}", stdout(&output));
+	let output = env.run_against(&server, &["state", "--automix-only"]);
+	assert_eq!(stdout(&output), "Automix Queue:\n  <A0> Automix Song\n");
+}
+
+#[test]
+fn state_can_be_rendered_with_a_template() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let template = env.dir.join("state.tmpl");
+	std::fs::write(&template, "{video.title} at {volume}%\n{#queue.items}{index}. {title} ({duration})\n{/queue.items}").unwrap();
+	let output = env.run_against(&server, &["state", "--template", template.to_str().unwrap()]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(stdout(&output), "Second Song at 50%\n0. First Song (3:21)\n1. Second Song (4:05)\n");
+}
+
+#[cfg(not(feature = "image"))]
+#[test]
+fn album_art_needs_the_image_feature() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["state", "--art-width", "40"]);
+	assert_eq!(output.status.code(), Some(1));
+	assert!(stderr(&output).contains("`image` feature"), "{}", stderr(&output));
+	assert!(server.requests().is_empty());
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn album_art_is_fetched_without_the_ytmd_headers() {
+	let server = MockServer::start(|request| match &*request.path {
+		"/api/v1/state" => {
+			let url = format!("http://{}/art.jpg", request.headers["host"]);
+			let thumbnails = serde_json::json!([{ "url": url, "width": 60, "height": 60 }]).to_string();
+			Response::json(200, &STATE_JSON.replace(r#""thumbnails": [],
+		"durationSeconds""#, &format!(r#""thumbnails": {thumbnails}, "durationSeconds""#)))
+		},
+		"/art.jpg" => Response::json(404, ""),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["state", "--art-width", "20", "--header", "X-Proxy-Auth: secret"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert!(stderr(&output).contains("Unable to load the album art"), "{}", stderr(&output));
+	let requests = server.requests();
+	let art = requests.iter().find(|r| r.path == "/art.jpg").unwrap();
+	assert!(!art.headers.contains_key("x-proxy-auth"));
+	assert!(art.headers["user-agent"].starts_with("ytmdctrl/"));
+}
+
+#[test]
+fn state_without_a_duration_shows_a_placeholder() {
+	let state = STATE_JSON.replace(r#""durationSeconds": 245"#, r#""durationSeconds": 0"#);
+	let live = state.replace(r#""isLive": false"#, r#""isLive": true"#);
+	let server = MockServer::start(move |request| match (&*request.path, request.headers.get("x-live")) {
+		("/api/v1/state", None) => Response::json(200, &state),
+		("/api/v1/state", Some(_)) => Response::json(200, &live),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["state"]);
+	assert!(stdout(&output).contains("Progress: 1:05/--:--\n"), "{}", stdout(&output));
+	let output = env.run_against(&server, &["state", "--compact"]);
+	assert!(stdout(&output).contains("1:05/--:--"), "{}", stdout(&output));
+	let output = env.run_against(&server, &["state", "--header", "X-Live: 1"]);
+	assert!(stdout(&output).contains("Progress: 1:05/live\n"), "{}", stdout(&output));
+}
+
+#[test]
+fn state_script_mode_keeps_full_precision() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["state", "--script"]);
+	assert!(stdout(&output).contains("Progress: 65.5s/245s\n"), "{}", stdout(&output));
+	assert!(stdout(&output).contains(r#"Status: playing "Second Song""#), "{}", stdout(&output));
+}
+
+#[test]
+fn state_compact_fits_on_one_line() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["state", "--compact"]);
+	assert_eq!(stdout(&output), "▶ Artist B - Second Song  1:05/4:05  vol 50%\n");
+	let output = env.run_against(&server, &["state", "--compact", "--no-unicode"]);
+	assert_eq!(stdout(&output), "> Artist B - Second Song  1:05/4:05  vol 50%\n");
+}
+
+#[test]
+fn state_field_prints_a_single_value() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["state", "--field", "video.title"]);
+	assert_eq!(stdout(&output), "Second Song\n");
+	let output = env.run_against(&server, &["state", "--field", "queue.selected_index"]);
+	assert_eq!(stdout(&output), "1\n");
+	let output = env.run_against(&server, &["state", "--field", "video.nonexistent"]);
+	assert_eq!(output.status.code(), Some(4));
+}
+
+#[test]
+fn playlists_are_listed() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["playlists"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(stdout(&output), "Road Trip -> PL1\nFocus -> PL2\n");
+}
+
+#[test]
+fn playlists_script_mode_escapes_titles() {
+	let server = MockServer::start(|request| match &*request.path {
+		"/api/v1/playlists" => Response::json(200, r#"[{"id":"PL1","title":"Left\tRight -> Center"},{"id":"PL2","title":"Two\nLines \\o/"}]"#),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["playlists", "--script"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(stdout(&output), "PL1\tLeft\\tRight -> Center\nPL2\tTwo\\nLines \\\\o/\n");
+}
+
+#[test]
+fn commands_are_posted_with_a_body() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["volume", "30"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+
+	let requests = server.requests();
+	assert_eq!(requests.len(), 1);
+	assert_eq!(requests[0].method, "POST");
+	assert_eq!(requests[0].path, "/api/v1/command");
+	let body: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+	assert_eq!(body, serde_json::json!({ "command": "setVolume", "data": 30 }));
+}
+
+#[test]
+fn options_take_their_value_either_way() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let port = server.port.to_string();
+	let port_option = format!("--port={port}");
+	for args in [
+		&["volume", "30", "--server", "127.0.0.1", "--port", &port, "--delay", "10ms"][..],
+		&["volume", "30", "--server=127.0.0.1", "--port", &port, "--delay", "10ms"],
+		&["volume", "30", "--server", "127.0.0.1", &port_option, "--delay=10ms"],
+	] {
+		let output = env.run(args);
+		assert!(output.status.success(), "{args:?}: {}", stderr(&output));
+	}
+	assert_eq!(server.requests().len(), 3);
+
+	let output = env.run_against(&server, &["volume", "30", "--delay=soon"]);
+	assert_eq!(output.status.code(), Some(1));
+	assert!(stderr(&output).contains("soon"), "{}", stderr(&output));
+
+	for args in [&["open", "--video", "abc", "--playlist", "PL2"][..], &["open", "--video=abc", "--playlist=PL2"]] {
+		let output = env.run_against(&server, args);
+		assert!(output.status.success(), "{args:?}: {}", stderr(&output));
+		let body: serde_json::Value = serde_json::from_str(&server.requests().last().unwrap().body).unwrap();
+		assert_eq!(body["data"], serde_json::json!({ "videoId": "abc", "playlistId": "PL2" }));
+	}
+}
+
+#[test]
+fn next_can_skip_several_songs() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["next", "3"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	let requests = server.requests();
+	let bodies: Vec<_> = requests.iter().map(|r| &*r.body).collect();
+	assert_eq!(bodies, vec![r#"{"command":"next"}"#; 3]);
+	// spaced out to stay under YTMD's rate limit of about two commands a second
+	for pair in requests.windows(2) {
+		assert!(pair[1].at - pair[0].at >= std::time::Duration::from_millis(500), "{:?}", pair[1].at - pair[0].at);
+	}
+
+	let output = env.run_against(&server, &["previous", "0"]);
+	assert_eq!(output.status.code(), Some(1));
+	assert_eq!(server.requests().len(), 3);
+}
+
+#[test]
+fn a_failed_skip_reports_how_far_it_got() {
+	let count = std::sync::atomic::AtomicUsize::new(0);
+	let server = MockServer::start(move |request| match &*request.path {
+		"/api/v1/command" if count.fetch_add(1, std::sync::atomic::Ordering::SeqCst) >= 2 => Response::json(500, r#"{"error":"INTERNAL"}"#),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["next", "5"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(stderr(&output).contains("Skipped 2 of 5 songs"), "{}", stderr(&output));
+	assert_eq!(server.requests().len(), 3);
+}
+
+#[test]
+fn mute_confirm_checks_the_player_was_muted() {
+	let muted = STATE_JSON.replace(r#""volume": 50,"#, r#""volume": 50, "muted": true,"#);
+	let server = MockServer::start(move |request| match &*request.path {
+		"/api/v1/state" => Response::json(200, &muted),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["mute", "--confirm"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	let requests = server.requests();
+	assert_eq!(requests[0].body, r#"{"command":"mute"}"#);
+	assert_eq!(requests[1].path, "/api/v1/state");
+
+	// the player stayed muted, so unmuting didn't take
+	let output = env.run_against(&server, &["unmute", "--confirm"]);
+	assert_eq!(output.status.code(), Some(3));
+	assert!(stderr(&output).contains("did not take effect"), "{}", stderr(&output));
+
+	// YTMD not saying is no confirmation either
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["mute", "--confirm"]);
+	assert_eq!(output.status.code(), Some(3));
+	assert!(stderr(&output).contains("did not report a mute status"), "{}", stderr(&output));
+}
+
+#[test]
+fn mute_toggle_restores_the_volume_it_muted_from() {
+	let state = STATE_JSON.replace(r#""volume": 50"#, r#""volume": 35"#);
+	let muted = STATE_JSON.replace(r#""volume": 50"#, r#""volume": 0"#);
+	let server = MockServer::start(move |request| match (&*request.path, request.headers.get("x-muted")) {
+		("/api/v1/state", None) => Response::json(200, &state),
+		("/api/v1/state", Some(_)) => Response::json(200, &muted),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let set_volumes = |server: &MockServer| -> Vec<serde_json::Value> {
+		server.requests().into_iter()
+			.filter(|r| r.path == "/api/v1/command")
+			.map(|r| serde_json::from_str::<serde_json::Value>(&r.body).unwrap()["data"].clone())
+			.collect()
+	};
+	for _ in 0..2 {
+		let output = env.run_against(&server, &["mute-toggle"]);
+		assert!(output.status.success(), "{}", stderr(&output));
+	}
+	assert_eq!(set_volumes(&server), vec![serde_json::json!(0), serde_json::json!(35)]);
+
+	// with nothing saved, a player already at 0 is turned back up
+	let output = env.run_against(&server, &["mute-toggle", "--header", "X-Muted: 1"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(set_volumes(&server).last(), Some(&serde_json::json!(50)));
+
+	// another server on the same host has a volume of its own to restore
+	let other = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN), (&other.addr(), TOKEN)]);
+	for server in [&server, &other, &server, &other] {
+		let output = env.run_against(server, &["mute-toggle"]);
+		assert!(output.status.success(), "{}", stderr(&output));
+	}
+	assert_eq!(set_volumes(&server)[3..], [serde_json::json!(0), serde_json::json!(35)]);
+	assert_eq!(set_volumes(&other), [serde_json::json!(0), serde_json::json!(50)]);
+}
+
+#[test]
+fn ensure_commands_only_act_when_needed() {
+	let paused = STATE_JSON.replace(r#""trackState": 1"#, r#""trackState": 0"#);
+	let server = MockServer::start(move |request| match (&*request.path, request.headers.get("x-paused")) {
+		("/api/v1/state", Some(_)) => Response::json(200, &paused),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let commands = |server: &MockServer| server.requests().into_iter().filter(|r| r.method == "POST").map(|r| r.body).collect::<Vec<_>>();
+	for args in [&["ensure-playing"][..], &["ensure-paused", "--header", "X-Paused: 1"]] {
+		let output = env.run_against(&server, args);
+		assert!(output.status.success(), "{}", stderr(&output));
+	}
+	assert!(commands(&server).is_empty());
+
+	for args in [&["ensure-paused"][..], &["ensure-playing", "--header", "X-Paused: 1"]] {
+		let output = env.run_against(&server, args);
+		assert!(output.status.success(), "{}", stderr(&output));
+	}
+	assert_eq!(commands(&server), [r#"{"command":"pause"}"#, r#"{"command":"play"}"#]);
+}
+
+#[test]
+fn raw_posts_the_body_as_given() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["raw", r#"{"command":"someNewThing","data":1}"#]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(server.requests()[0].body, r#"{"command":"someNewThing","data":1}"#);
+
+	let output = env.run_against(&server, &["raw", r#"{"command":"#]);
+	assert_eq!(output.status.code(), Some(1));
+	assert!(stderr(&output).contains("valid JSON"), "{}", stderr(&output));
+	assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn get_fetches_any_api_path() {
+	let server = MockServer::start(|request| match &*request.path {
+		"/api/v1/playlists/PL1" => Response::json(200, r#"{"id":"PL1","title":"Road Trip"}"#),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["get", "/playlists/PL1"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(stdout(&output), "{\n  \"id\": \"PL1\",\n  \"title\": \"Road Trip\"\n}\n");
+	assert_eq!(server.requests()[0].headers.get("authorization").map(String::as_str), Some(TOKEN));
+
+	let output = env.run_against(&server, &["get", "/playlists/PL1", "--no-pretty"]);
+	assert_eq!(stdout(&output), "{\"id\":\"PL1\",\"title\":\"Road Trip\"}\n");
+
+	for path in ["../auth/requestcode", "@evil.example/state", "state?x=1"] {
+		let output = env.run_against(&server, &["get", path]);
+		assert_eq!(output.status.code(), Some(1), "{path}");
+	}
+	assert_eq!(server.requests().len(), 2);
+}
+
+#[test]
+fn interrupting_a_delay_cancels_cleanly() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let child = env.spawn_against(&server, &["pause", "--delay", "1h"]);
+	std::thread::sleep(std::time::Duration::from_millis(500));
+	let killed = std::process::Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+	assert!(killed.success());
+	let output = child.wait_with_output().unwrap();
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(stderr(&output), "cancelled\n");
+	assert!(server.requests().is_empty());
+}
+
+#[test]
+fn like_next_likes_then_skips() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["like-next"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	let bodies: Vec<String> = server.requests().into_iter().map(|r| r.body).collect();
+	assert_eq!(bodies, [r#"{"command":"toggleLike"}"#, r#"{"command":"next"}"#]);
+}
+
+#[test]
+fn requests_carry_user_agent_and_extra_headers() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["play", "--header", "X-Proxy-Auth: secret", "--header", "X-Other:1"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	let headers = &server.requests()[0].headers;
+	assert!(headers["user-agent"].starts_with("ytmdctrl/"), "{headers:?}");
+	assert_eq!(headers["x-proxy-auth"], "secret");
+	assert_eq!(headers["x-other"], "1");
+
+	let output = env.run_against(&server, &["play", "--header", "no colon"]);
+	assert_eq!(output.status.code(), Some(1));
+	assert!(stderr(&output).contains("Invalid header `no colon`"), "{}", stderr(&output));
+	assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn volume_steps_stop_at_the_limit() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	// the mock's volume is 50
+	env.run_against(&server, &["volume-up", "--max-volume", "55"]);
+	env.run_against(&server, &["volume-up", "--max-volume", "70"]);
+	env.run_against(&server, &["volume-down", "--min-volume", "45"]);
+	let bodies: Vec<String> = server.requests().into_iter().filter(|r| r.method == "POST").map(|r| r.body).collect();
+	assert_eq!(bodies, [
+		r#"{"command":"setVolume", "data": 55}"#,
+		r#"{"command":"volumeUp"}"#,
+		r#"{"command":"setVolume", "data": 45}"#,
+	]);
+}
+
+#[test]
+fn shuffle_needs_yes_in_script_mode() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["shuffle", "--script"]);
+	assert_eq!(output.status.code(), Some(3));
+	assert!(server.requests().is_empty());
+
+	let output = env.run_against(&server, &["shuffle", "--script", "--yes"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	// without a terminal to ask on, there's no prompt either
+	let output = env.run_against(&server, &["shuffle"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(server.requests().len(), 2);
+}
+
+#[test]
+fn jumpto_can_wait_for_the_queue_to_generate() {
+	let generating = STATE_JSON.replace(r#""isGenerating": false"#, r#""isGenerating": true"#);
+	let polls = std::sync::atomic::AtomicUsize::new(0);
+	let server = MockServer::start(move |request| match &*request.path {
+		"/api/v1/state" if polls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < 2 => Response::json(200, &generating),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["jumpto", "5", "--wait-generate"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	let requests = server.requests();
+	let paths: Vec<&str> = requests.iter().map(|r| &*r.path).collect();
+	assert_eq!(paths, ["/api/v1/state", "/api/v1/state", "/api/v1/state", "/api/v1/command"]);
+	assert_eq!(requests[3].body, r#"{"command":"playQueueIndex", "data": 5}"#);
+}
+
+#[test]
+fn track_info_includes_the_ids() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["track-info"]);
+	assert_eq!(stdout(&output), "author=Artist B\ntitle=Second Song\nalbum=Some Album\nalbum_id=album1\nchannel_id=chan1\nid=vid1\nvideo_type=audio\n");
+
+	let output = env.run_against(&server, &["track-info", "--json"]);
+	let parsed: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+	assert_eq!(parsed["album_id"], "album1");
+	assert_eq!(parsed["channel_id"], "chan1");
+}
+
+#[test]
+fn seek_accepts_timestamps() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["seek", "1:30"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(server.requests().last().unwrap().body, r#"{"command":"seekTo", "data": 90}"#);
+}
+
+#[test]
+fn play_can_wait_until_playback_starts() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["play", "--wait-until-playing"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+	assert_eq!(paths, ["/api/v1/command", "/api/v1/state"]);
+}
+
+#[test]
+fn wait_track_end_returns_when_the_song_changes() {
+	let next_song = STATE_JSON.replace(r#""id": "vid1""#, r#""id": "vid2""#);
+	let polls = std::sync::atomic::AtomicUsize::new(0);
+	let server = MockServer::start(move |request| match &*request.path {
+		"/api/v1/state" if polls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) >= 2 => Response::json(200, &next_song),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["wait-track-end", "--poll-interval", "0.2"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(server.requests().len(), 3);
+
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["wait-track-end", "--poll-interval", "0.2", "--timeout", "0.5"]);
+	assert_eq!(output.status.code(), Some(10));
+}
+
+#[test]
+fn rate_limit_reports_wait_time() {
+	let server = MockServer::start(|_| Response::json(429, "{}").with_header("x-ratelimit-reset", "3"));
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["play"]);
+	let err = stderr(&output);
+	assert!(err.contains("Rate limit exceeded"), "{err}");
+	assert!(err.contains("Wait 3 seconds"), "{err}");
+	// a rate limited request still means the token is good
+	assert_eq!(env.tokens().get(&server.addr()).map(String::as_str), Some(TOKEN));
+}
+
+#[test]
+fn response_headers_can_be_dumped() {
+	let server = MockServer::start(|_| Response::json(429, "{}").with_header("x-ratelimit-remaining", "0").with_header("x-ratelimit-reset", "3"));
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["play", "--dump-headers"]);
+	let err = stderr(&output);
+	assert!(err.contains("429 Too Many Requests"), "{err}");
+	assert!(err.contains("x-ratelimit-remaining: 0\n"), "{err}");
+	assert!(err.contains("x-ratelimit-reset: 3\n"), "{err}");
+
+	let output = env.run_against(&server, &["play"]);
+	assert!(!stderr(&output).contains("x-ratelimit-remaining"), "{}", stderr(&output));
+}
+
+#[test]
+fn runs_can_be_logged() {
+	let server = MockServer::start(|request| match &*request.body {
+		r#"{"command":"next"}"# => Response::json(500, r#"{"error":"INTERNAL"}"#),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let log = env.dir.join("runs.log");
+	let log_arg = log.to_str().unwrap();
+	let output = env.run_against(&server, &["pause", "--log", log_arg, "--header=X-Proxy-Auth: hunter2"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	let output = env.run_against(&server, &["next", "--log", log_arg]);
+	assert_eq!(output.status.code(), Some(2));
+
+	let contents = std::fs::read_to_string(&log).unwrap();
+	let lines: Vec<_> = contents.lines().collect();
+	assert_eq!(lines.len(), 2, "{contents}");
+	let server_addr = format!("127.0.0.1:{}", server.port);
+	assert!(lines[0].ends_with(&format!("on {server_addr}: exit 0")), "{contents}");
+	assert!(lines[0].contains(&format!("`pause --log {log_arg} --header X-Proxy-Auth: <redacted> --server")), "{contents}");
+	assert!(!contents.contains("hunter2"), "{contents}");
+	assert!(lines[1].ends_with(&format!("on {server_addr}: exit 2")), "{contents}");
+	use std::os::unix::fs::PermissionsExt as _;
+	assert_eq!(std::fs::metadata(&log).unwrap().permissions().mode() & 0o777, 0o600);
+}
+
+#[test]
+fn unauthorized_deletes_only_that_servers_token() {
+	let server = MockServer::start(|_| Response::json(401, r#"{"error":"UNAUTHORIZED"}"#));
+	// the companion server on the default port of the same host has a token of its own
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN), ("127.0.0.1", "default-port-token"), ("other-host", "other-token")]);
+	let output = env.run_against(&server, &["play"]);
+	assert!(stderr(&output).contains("deleting token"), "{}", stderr(&output));
+
+	let tokens = env.tokens();
+	assert!(!tokens.contains_key(&server.addr()));
+	assert_eq!(tokens.get("127.0.0.1").map(String::as_str), Some("default-port-token"));
+	assert_eq!(tokens.get("other-host").map(String::as_str), Some("other-token"));
+}
+
+#[test]
+fn other_errors_exit_with_command_failed() {
+	let server = MockServer::start(|_| Response::json(500, r#"{"error":"INTERNAL"}"#));
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["play"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(stderr(&output).contains("  \"error\": \"INTERNAL\""), "{}", stderr(&output));
+
+	let output = env.run_against(&server, &["play", "--no-pretty"]);
+	assert!(stderr(&output).contains("{\"error\":\"INTERNAL\"}"), "{}", stderr(&output));
+}
+
+#[test]
+fn switch_version_opens_the_counterpart_at_the_same_position() {
+	let state = STATE_JSON.replace(
+		r#""videoId": "vid1", "counterparts": null"#,
+		r#""videoId": "vid1", "counterparts": [{ "thumbnails": [], "title": "Second Song (Video)", "author": "Artist B", "duration": "4:05", "selected": false, "videoId": "vid1v", "counterparts": null }]"#,
+	);
+	let server = MockServer::start(move |request| match &*request.path {
+		"/api/v1/state" => Response::json(200, &state),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["switch-version"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+
+	let bodies: Vec<serde_json::Value> = server.requests().iter()
+		.filter(|r| r.method == "POST")
+		.map(|r| serde_json::from_str(&r.body).unwrap())
+		.collect();
+	assert_eq!(bodies, [
+		serde_json::json!({ "command": "changeVideo", "data": { "videoId": "vid1v", "playlistId": null } }),
+		serde_json::json!({ "command": "seekTo", "data": 65.5 }),
+	]);
+}
+
+#[test]
+fn switch_version_without_counterpart_does_nothing() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["switch-version"]);
+	assert!(output.status.success());
+	assert!(stderr(&output).contains("no alternate"), "{}", stderr(&output));
+	assert!(server.requests().iter().all(|r| r.method == "GET"));
+}
+
+#[test]
+fn unknown_server_lists_known_servers() {
+	let server = MockServer::start(|_| Response::json(403, r#"{"error":"AUTHORIZATION_DISABLED"}"#));
+	let env = TestEnv::with_tokens(&[("localhost", TOKEN), ("192.168.1.42", TOKEN)]);
+	let output = env.run_against(&server, &["play"]);
+	let err = stderr(&output);
+	assert!(err.contains(&format!("No token stored for `{}`; tokens are stored for: 192.168.1.42, localhost", server.addr())), "{err}");
+}
+
+#[test]
+fn address_family_preference_is_enforced() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["play", "--ipv4"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	let output = env.run_against(&server, &["play", "--ipv6"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(stderr(&output).contains("Unable to find an IPv6 address for `127.0.0.1`"), "{}", stderr(&output));
+}
+
+#[test]
+fn html_pages_are_not_dumped() {
+	let server = MockServer::start(|_| Response {
+		status: 200,
+		headers: vec![("content-type".into(), "text/html; charset=utf-8".into())],
+		body: "<html><body>Router login</body></html>".into(),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["state"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert_eq!(stdout(&output), "");
+	let err = stderr(&output);
+	assert!(err.contains("doesn't look like YTMD (it responded with text/html; charset=utf-8)"), "{err}");
+	assert!(!err.contains("Router login"), "{err}");
+}
+
+#[test]
+fn redirects_are_not_followed() {
+	let server = MockServer::start(|_| Response { status: 302, headers: vec![("location".into(), "/login".into())], body: String::new() });
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["play"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(stderr(&output).contains("it redirected to /login"), "{}", stderr(&output));
+}
+
+#[test]
+fn auth_handshake_uses_custom_app_identity() {
+	let server = MockServer::start(|request| match &*request.path {
+		"/api/v1/auth/requestcode" => Response::json(200, r#"{"code":"1234"}"#),
+		"/api/v1/auth/request" => Response::json(200, r#"{"token":"new-token"}"#),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[]);
+	let output = env.run_against(&server, &["play", "--app-name", "Living Room Keys", "--app-id", "ytmdctrl-keys"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert!(stderr(&output).contains("tied to the app id"));
+
+	let requests = server.requests();
+	let code_request: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+	assert_eq!(code_request["appName"], "Living Room Keys");
+	assert_eq!(code_request["appId"], "ytmdctrl-keys");
+	let token_request: serde_json::Value = serde_json::from_str(&requests[1].body).unwrap();
+	assert_eq!(token_request, serde_json::json!({ "appId": "ytmdctrl-keys", "code": "1234" }));
+	assert_eq!(requests[2].headers.get("authorization").map(String::as_str), Some("new-token"));
+	assert_eq!(env.tokens().get(&server.addr()).map(String::as_str), Some("new-token"));
+}
+
+#[test]
+fn setup_problems_get_tailored_messages() {
+	let server = MockServer::start(|request| match &*request.path {
+		"/api/v1/auth/requestcode" => Response::json(403, r#"{"error":"AUTHORIZATION_DISABLED"}"#),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[]);
+	let output = env.run_against(&server, &["play"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(stderr(&output).contains("companion authorization is disabled"), "{}", stderr(&output));
+
+	// nothing listens on port 1
+	let output = env.run(&["play", "--server", "127.0.0.1", "--port", "1"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(stderr(&output).contains("Unable to connect to YTMD"), "{}", stderr(&output));
+	assert!(!stderr(&output).contains("panicked"), "{}", stderr(&output));
+}
+
+#[test]
+fn a_newer_api_version_is_pointed_out() {
+	let server = MockServer::start(|request| match &*request.path {
+		"/metadata" => Response::json(200, r#"{"apiVersions":["v2"]}"#),
+		_ => Response::json(404, r#"{"error":"NOT_FOUND"}"#),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["play"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(stderr(&output).contains("only supports v2"), "{}", stderr(&output));
+
+	let output = TestEnv::with_tokens(&[]).run_against(&server, &["play"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(stderr(&output).contains("only supports v2"), "{}", stderr(&output));
+}
+
+#[test]
+fn wait_connection_gives_up_after_its_timeout() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["play", "--wait-connection", "5s"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(server.requests().last().unwrap().body, r#"{"command":"play"}"#);
+
+	// nothing listens on port 1
+	let started = std::time::Instant::now();
+	let output = env.run(&["play", "--server", "127.0.0.1", "--port", "1", "--wait-connection", "1s"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(stderr(&output).contains("Timed out after 0:01"), "{}", stderr(&output));
+	assert!(started.elapsed() < std::time::Duration::from_secs(5));
+}
+
+#[test]
+fn seek_refuses_live_streams_unless_forced() {
+	let state = STATE_JSON.replace(r#""isLive": false"#, r#""isLive": true"#);
+	let server = MockServer::start(move |request| match &*request.path {
+		"/api/v1/state" => Response::json(200, &state),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["seek", "30"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(stderr(&output).contains("Refusing to seek in a live stream"));
+	assert!(server.requests().iter().all(|r| r.method == "GET"));
+
+	let output = env.run_against(&server, &["seek", "30", "--force"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(server.requests().last().unwrap().body, r#"{"command":"seekTo", "data": 30}"#);
+}
+
+#[test]
+fn seek_and_jumpto_go_ahead_when_the_state_is_rate_limited() {
+	let server = MockServer::start(|request| match &*request.path {
+		"/api/v1/state" => Response::json(429, "{}").with_header("x-ratelimit-reset", "3"),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["seek", "30"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(server.requests().last().unwrap().body, r#"{"command":"seekTo", "data": 30}"#);
+
+	// jumping away from a live stream is harmless, so `jumpto` doesn't read the state at all
+	let output = env.run_against(&server, &["jumpto", "2"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	let requests = server.requests();
+	assert_eq!(requests.len(), 3);
+	assert_eq!(requests[2].body, r#"{"command":"playQueueIndex", "data": 2}"#);
+}
+
+#[test]
+fn server_urls_are_normalized() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run(&["play", "--server", &format!("http://127.0.0.1:{}/", server.port)]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(server.requests().len(), 1);
+
+	let output = env.run(&["play", "--server", "not a host"]);
+	assert_eq!(output.status.code(), Some(1));
+	assert!(stderr(&output).contains("Invalid value `not a host` for `--server`"), "{}", stderr(&output));
+}
+
+#[test]
+fn server_aliases_share_tokens_with_their_address() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	env.write_config(&format!("[servers]\nmock = \"127.0.0.1:{}\"\n", server.port));
+	let output = env.run(&["play", "--server", "mock"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(server.requests()[0].headers.get("authorization").map(String::as_str), Some(TOKEN));
+}
+
+#[test]
+fn config_gives_commands_default_options() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	env.write_config("[commands.state]\ncompact = true\n\n[commands.volume-up]\nmax-volume = 55\n");
+	let output = env.run_against(&server, &["state"]);
+	assert_eq!(stdout(&output).lines().count(), 1, "{}", stdout(&output));
+
+	// options given on the command line win over the config's
+	for (args, volume) in [(&["volume-up"][..], 55), (&["volume-up", "--max-volume", "58"], 58)] {
+		let output = env.run_against(&server, args);
+		assert!(output.status.success(), "{}", stderr(&output));
+		assert_eq!(server.requests().last().unwrap().body, format!(r#"{{"command":"setVolume", "data": {volume}}}"#));
+	}
+
+	env.write_config("[commands.play]\nno-such-option = true\n");
+	let output = env.run_against(&server, &["play"]);
+	assert_eq!(output.status.code(), Some(1));
+	assert!(stderr(&output).contains("[commands.play] in config.toml"), "{}", stderr(&output));
+}
+
+#[test]
+fn remaining_sums_the_rest_of_the_queue() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	// the selected song is the last one, with 245 - 65.5 seconds left
+	let output = env.run_against(&server, &["remaining"]);
+	assert_eq!(stdout(&output), "2:59\n");
+	let output = env.run_against(&server, &["remaining", "--script"]);
+	assert_eq!(stdout(&output), "179.5\n");
+
+	let state = STATE_JSON.replace(r#""selectedItemIndex": 1"#, r#""selectedItemIndex": 0"#)
+		.replace(r#""isInfinite": false"#, r#""isInfinite": true"#);
+	let server = MockServer::start(move |request| match &*request.path {
+		"/api/v1/state" => Response::json(200, &state),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["remaining"]);
+	// 179.5s of the current song plus the 4:05 one after it
+	assert_eq!(stdout(&output), "7:04+\n");
+}
+
+#[test]
+fn state_since_detects_song_changes() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["state", "--since", "vid1"]);
+	assert_eq!(output.status.code(), Some(10));
+	assert_eq!(stdout(&output), "");
+
+	let output = env.run_against(&server, &["state", "--since", "vid0", "--field", "video.id"]);
+	assert_eq!(output.status.code(), Some(0));
+	assert_eq!(stdout(&output), "vid1\n");
+}
+
+#[test]
+fn open_can_start_at_an_index() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["open", "--playlist", "PL1", "--index", "1"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+
+	let requests = server.requests();
+	let methods: Vec<_> = requests.iter().map(|r| (&*r.method, &*r.path)).collect();
+	assert_eq!(methods, [("POST", "/api/v1/command"), ("GET", "/api/v1/state"), ("POST", "/api/v1/command")]);
+	assert_eq!(requests[2].body, r#"{"command":"playQueueIndex", "data": 1}"#);
+}
+
+#[test]
+fn open_can_start_playback_once_loaded() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["open", "--playlist", "PL2", "--play"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+
+	let requests = server.requests();
+	let methods: Vec<_> = requests.iter().map(|r| (&*r.method, &*r.path)).collect();
+	assert_eq!(methods, [("POST", "/api/v1/command"), ("GET", "/api/v1/state"), ("POST", "/api/v1/command")]);
+	assert_eq!(requests[2].body, r#"{"command":"play"}"#);
+}
+
+#[test]
+fn open_refuses_a_song_missing_from_the_loaded_playlist() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["open", "--video", "elsewhere", "--playlist", "PL1"]);
+	assert_eq!(output.status.code(), Some(1));
+	assert!(stderr(&output).contains("isn't on playlist `PL1`"), "{}", stderr(&output));
+	assert!(server.requests().iter().all(|r| r.method == "GET"));
+
+	let output = env.run_against(&server, &["open", "--video", "vid0", "--playlist", "PL1"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(server.requests().last().unwrap().method, "POST");
+}
+
+#[test]
+fn open_goes_ahead_when_the_state_is_rate_limited() {
+	let server = MockServer::start(|request| match &*request.path {
+		"/api/v1/state" => Response::json(429, "{}").with_header("x-ratelimit-reset", "3"),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let output = env.run_against(&server, &["open", "--video", "elsewhere", "--playlist", "PL1"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	let body: serde_json::Value = serde_json::from_str(&server.requests().last().unwrap().body).unwrap();
+	assert_eq!(body, serde_json::json!({ "command": "changeVideo", "data": { "videoId": "elsewhere", "playlistId": "PL1" } }));
+}
+
+#[test]
+fn is_liked_maps_like_status_to_exit_code() {
+	let cases = [(2, "👍 Liked", "like", 0), (1, "Indifferent", "indifferent", 1), (0, "👎 Disliked", "dislike", 2), (-1, "? Unknown", "unknown", 11)];
+	for (status, name, script_name, code) in cases {
+		let state = STATE_JSON.replace(r#""likeStatus": 1"#, &format!(r#""likeStatus": {status}"#));
+		let server = MockServer::start(move |request| match &*request.path {
+			"/api/v1/state" => Response::json(200, &state),
+			_ => ytmd(request),
+		});
+		let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+		let output = env.run_against(&server, &["is-liked"]);
+		assert_eq!(output.status.code(), Some(code));
+		assert_eq!(stdout(&output), format!("{name}\n"));
+		let output = env.run_against(&server, &["is-liked", "--script"]);
+		assert_eq!(output.status.code(), Some(code));
+		assert_eq!(stdout(&output), format!("{script_name}\n"));
+		let output = env.run_against(&server, &["is-liked", "--quiet"]);
+		assert_eq!(output.status.code(), Some(code));
+		assert_eq!(stdout(&output), "");
+	}
+}
+
+#[test]
+fn watch_survives_its_fifo_reader_going_away() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let fifo = env.dir.join("status");
+	assert!(std::process::Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+	let fifo_arg = fifo.to_str().unwrap();
+	let mut child = env.spawn_against(&server, &["watch", "--fifo", fifo_arg]);
+	for _ in 0..2 {
+		// each reader gets the current status, even though it hasn't changed in between
+		let mut reader = BufReader::new(std::fs::File::open(&fifo).unwrap());
+		let mut line = String::new();
+		reader.read_line(&mut line).unwrap();
+		assert_eq!(line, "Playing: Artist B - Second Song\n");
+	}
+	assert!(child.try_wait().unwrap().is_none());
+	child.kill().unwrap();
+	child.wait().unwrap();
+}
+
+#[test]
+fn run_executes_each_script_line() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let script = env.dir.join("script.txt");
+	std::fs::write(&script, "# set things up\nvolume 30\n\nplay --delay 100ms\n").unwrap();
+	let output = env.run_against(&server, &["run", script.to_str().unwrap()]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	let bodies: Vec<String> = server.requests().into_iter().map(|r| r.body).collect();
+	assert_eq!(bodies, [r#"{"command":"setVolume", "data": 30}"#, r#"{"command":"play"}"#]);
+}
+
+#[test]
+fn run_checks_every_line_before_starting() {
+	let server = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let script = env.dir.join("script.txt");
+	std::fs::write(&script, "volume 30\nplay --delay soon\n").unwrap();
+	let output = env.run_against(&server, &["run", script.to_str().unwrap()]);
+	assert_eq!(output.status.code(), Some(1));
+	assert!(stderr(&output).contains("script.txt:2: Invalid delay `soon`"), "{}", stderr(&output));
+	assert!(server.requests().is_empty());
+}
+
+#[test]
+fn run_stops_at_a_failing_line_unless_told_to_continue() {
+	let server = MockServer::start(|request| match &*request.body {
+		r#"{"command":"next"}"# => Response::json(500, r#"{"error":"INTERNAL"}"#),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let script = env.dir.join("script.txt");
+	std::fs::write(&script, "next\nplay\n").unwrap();
+	let script = script.to_str().unwrap();
+
+	let output = env.run_against(&server, &["run", script]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(stderr(&output).contains("script.txt:1: failed with exit code 2"), "{}", stderr(&output));
+	assert_eq!(server.requests().len(), 1);
+
+	let output = env.run_against(&server, &["run", script, "--continue-on-error"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert_eq!(server.requests().last().unwrap().body, r#"{"command":"play"}"#);
+}
+
+#[test]
+fn run_reads_an_until_delay_past_a_default_delay() {
+	let server = MockServer::start(|request| match &*request.body {
+		r#"{"command":"next"}"# => Response::json(500, r#"{"error":"INTERNAL"}"#),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	env.write_config("[commands.play]\ndelay = \"2s\"\n");
+	let script = env.dir.join("script.txt");
+	// the failing first line stops the script before the second is waited on, so this only checks that it parses
+	std::fs::write(&script, "next\nplay --delay until 23:59\n").unwrap();
+	let output = env.run_against(&server, &["run", script.to_str().unwrap()]);
+	assert_eq!(output.status.code(), Some(2), "{}", stderr(&output));
+	assert!(stderr(&output).contains("script.txt:1: failed"), "{}", stderr(&output));
+}
+
+#[test]
+fn run_counts_a_rate_limited_line_as_failed() {
+	let server = MockServer::start(|request| match &*request.body {
+		r#"{"command":"next"}"# => Response::json(429, "{}").with_header("x-ratelimit-reset", "3"),
+		_ => ytmd(request),
+	});
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let script = env.dir.join("script.txt");
+	std::fs::write(&script, "volume 30
+next
+play
+").unwrap();
+	let output = env.run_against(&server, &["run", script.to_str().unwrap()]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(stderr(&output).contains("script.txt:2: failed with exit code 2"), "{}", stderr(&output));
+	let requests = server.requests();
+	assert_eq!(requests.len(), 2);
+	// spaced out like any other commands sent back to back
+	assert!(requests[1].at - requests[0].at >= std::time::Duration::from_millis(500), "{:?}", requests[1].at - requests[0].at);
+}
+
+#[test]
+fn run_stops_when_the_token_is_rejected() {
+	let server = MockServer::start(|_| Response::json(401, r#"{"error":"UNAUTHORIZED"}"#));
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let script = env.dir.join("script.txt");
+	std::fs::write(&script, "play\nnext\n").unwrap();
+	let output = env.run_against(&server, &["run", script.to_str().unwrap(), "--continue-on-error"]);
+	assert!(stderr(&output).contains("deleting token"), "{}", stderr(&output));
+	// no later line would get through with the same token
+	assert_eq!(server.requests().len(), 1);
+	assert!(!env.tokens().contains_key(&server.addr()));
+}
+
+#[test]
+fn via_daemon_runs_commands_through_the_daemon() {
+	let server = MockServer::start(|request| match &*request.body {
+		r#"{"command":"next"}"# => Response::json(500, r#"{"error":"INTERNAL"}"#),
+		_ => ytmd(request),
+	});
+	let other = MockServer::start(ytmd);
+	let env = TestEnv::with_tokens(&[(&server.addr(), TOKEN)]);
+	let mut daemon = env.spawn_against(&server, &["daemon"]);
+	let socket = env.dir.join("ytmdctrl").join("daemon.sock");
+	for _ in 0..50 {
+		if socket.exists() {
+			break;
+		}
+		std::thread::sleep(std::time::Duration::from_millis(100));
+	}
+	// the daemon has its token already, so without one of its own only the daemon could get a command through
+	std::fs::write(env.token_store_path(), serde_json::json!({ other.addr(): TOKEN }).to_string()).unwrap();
+	let output = env.run_against(&server, &["volume", "30", "--via-daemon"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(server.requests()[0].body, r#"{"command":"setVolume", "data": 30}"#);
+
+	let output = env.run_against(&server, &["next", "--via-daemon"]);
+	assert_eq!(output.status.code(), Some(2));
+
+	// `shuffle` is confirmed before it's handed over, rather than left to a daemon with no one to ask
+	let output = env.run_against(&server, &["shuffle", "--via-daemon", "--script"]);
+	assert_eq!(output.status.code(), Some(3));
+	assert_eq!(server.requests().len(), 2);
+	let output = env.run_against(&server, &["shuffle", "--via-daemon"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(server.requests()[2].body, r#"{"command":"shuffle"}"#);
+
+	// a command for another server is refused by the daemon and runs directly
+	let output = env.run_against(&other, &["pause", "--via-daemon"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(server.requests().len(), 3);
+	assert_eq!(other.requests()[0].body, r#"{"command":"pause"}"#);
+
+	// interrupting the delay cancels the command before the daemon ever gets it
+	let client = env.spawn_against(&server, &["play", "--via-daemon", "--delay", "1s"]);
+	std::thread::sleep(std::time::Duration::from_millis(300));
+	let killed = std::process::Command::new("kill").args(["-INT", &client.id().to_string()]).status().unwrap();
+	assert!(killed.success());
+	assert!(client.wait_with_output().unwrap().status.success());
+	std::thread::sleep(std::time::Duration::from_millis(1500));
+	assert_eq!(server.requests().len(), 3);
+	// and the daemon, which was never waiting on it, carries on
+	let output = env.run_against(&server, &["play", "--via-daemon", "--delay", "100ms"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(server.requests().last().unwrap().body, r#"{"command":"play"}"#);
+
+	// nor does it wait out a delay sent to it directly
+	use std::io::{BufRead as _, Write as _};
+	let mut stream = std::os::unix::net::UnixStream::connect(&socket).unwrap();
+	let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+	write!(stream, "{}\nplay --delay 5s\nshuffle\n", server.addr()).unwrap();
+	let mut replies = String::new();
+	reader.read_line(&mut replies).unwrap();
+	reader.read_line(&mut replies).unwrap();
+	reader.read_line(&mut replies).unwrap();
+	assert_eq!(replies, "ok\n1\n3\n");
+
+	daemon.kill().unwrap();
+	daemon.wait().unwrap();
+	// with the daemon gone, commands run directly again
+	let output = env.run_against(&other, &["play", "--via-daemon"]);
+	assert!(output.status.success(), "{}", stderr(&output));
+	assert_eq!(other.requests().last().unwrap().body, r#"{"command":"play"}"#);
+}